@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+// stesso schema Busy/Available di cache::Cache, ma chi attende una chiave Busy
+// viene sospeso come future invece di bloccare il thread su una condvar
+enum State<V> {
+    Busy(Arc<Mutex<Flight<V>>>),
+    Available(Arc<V>),
+}
+
+struct Flight<V> {
+    result: FlightResult<V>,
+    wakers: Vec<Waker>,
+}
+
+enum FlightResult<V> {
+    Pending,
+    Ready(Arc<V>),
+    // il calcolo è stato abbandonato (panic o future droppato): chi attende riprova
+    Abandoned,
+}
+
+pub struct AsyncCache<K: Clone + Eq + Hash, V> {
+    map: Arc<Mutex<HashMap<K, State<V>>>>,
+}
+
+impl<K: Clone + Eq + Hash, V> Clone for AsyncCache<K, V> {
+    fn clone(&self) -> Self {
+        AsyncCache { map: Arc::clone(&self.map) }
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> Default for AsyncCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> AsyncCache<K, V> {
+    pub fn new() -> Self {
+        AsyncCache { map: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub async fn get<F, Fut>(&self, key: K, f: F) -> Arc<V>
+    where F: FnOnce(K) -> Fut, Fut: Future<Output = V> {
+        let mut f = Some(f);
+        loop {
+            // (flight, true) se questo chiamante deve calcolare il valore
+            let (flight, owner) = {
+                let mut m = self.map.lock().unwrap();
+                match m.get(&key) {
+                    Some(State::Available(val)) => return Arc::clone(val),
+                    Some(State::Busy(flight)) => (Arc::clone(flight), false),
+                    None => {
+                        let flight = Arc::new(Mutex::new(Flight { result: FlightResult::Pending, wakers: Vec::new() }));
+                        m.insert(key.clone(), State::Busy(Arc::clone(&flight)));
+                        (flight, true)
+                    },
+                }
+            };
+
+            if owner {
+                let f = f.take().expect("compute closure already used");
+                return self.compute(key, flight, f).await;
+            }
+            if let Some(val) = (WaitFlight { flight }).await {
+                return val;
+            }
+        }
+    }
+
+    async fn compute<F, Fut>(&self, key: K, flight: Arc<Mutex<Flight<V>>>, f: F) -> Arc<V>
+    where F: FnOnce(K) -> Fut, Fut: Future<Output = V> {
+        let guard = FlightGuard { map: &self.map, key: &key, flight: &flight, armed: true };
+        let val = Arc::new(f(key.clone()).await);
+        guard.disarm();
+
+        self.map.lock().unwrap().insert(key, State::Available(Arc::clone(&val)));
+        let wakers = {
+            let mut fl = flight.lock().unwrap();
+            fl.result = FlightResult::Ready(Arc::clone(&val));
+            std::mem::take(&mut fl.wakers)
+        };
+        for w in wakers {
+            w.wake();
+        }
+        val
+    }
+}
+
+struct WaitFlight<V> {
+    flight: Arc<Mutex<Flight<V>>>,
+}
+
+impl<V> Future for WaitFlight<V> {
+    type Output = Option<Arc<V>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut fl = self.flight.lock().unwrap();
+        match &fl.result {
+            FlightResult::Ready(val) => Poll::Ready(Some(Arc::clone(val))),
+            FlightResult::Abandoned => Poll::Ready(None),
+            FlightResult::Pending => {
+                if !fl.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    fl.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            },
+        }
+    }
+}
+
+// se il future di calcolo va in panic o viene droppato prima di finire, libera la chiave
+struct FlightGuard<'a, K: Eq + Hash, V> {
+    map: &'a Mutex<HashMap<K, State<V>>>,
+    key: &'a K,
+    flight: &'a Arc<Mutex<Flight<V>>>,
+    armed: bool,
+}
+
+impl<K: Eq + Hash, V> FlightGuard<'_, K, V> {
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for FlightGuard<'_, K, V> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let mut m = self.map.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(m.get(self.key), Some(State::Busy(fl)) if Arc::ptr_eq(fl, self.flight)) {
+            m.remove(self.key);
+        }
+        drop(m);
+
+        let wakers = {
+            let mut fl = self.flight.lock().unwrap_or_else(|e| e.into_inner());
+            fl.result = FlightResult::Abandoned;
+            std::mem::take(&mut fl.wakers)
+        };
+        for w in wakers {
+            w.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{block_on, join_all, sleep};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_concurrent_callers_share_one_computation() {
+        let cache = AsyncCache::<u32, u64>::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let futs: Vec<_> = (0..5).map(|_| {
+            let cache = cache.clone();
+            let calls = Arc::clone(&calls);
+            async move {
+                *cache.get(7, |k| async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    sleep(Duration::from_millis(50)).await;
+                    k as u64 * 10
+                }).await
+            }
+        }).collect();
+
+        assert_eq!(join_all(futs), vec![70; 5]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_cached_value_is_returned_without_computing() {
+        let cache = AsyncCache::<u32, u32>::new();
+        assert_eq!(*block_on(cache.get(1, |k| async move { k + 1 })), 2);
+        assert_eq!(*block_on(cache.get(1, |_| async move { unreachable!() })), 2);
+    }
+
+    #[test]
+    fn test_waiters_across_threads() {
+        let cache = AsyncCache::<u32, u32>::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..4).map(|_| {
+            let cache = cache.clone();
+            let calls = Arc::clone(&calls);
+            thread::spawn(move || {
+                *block_on(cache.get(3, |k| async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    sleep(Duration::from_millis(50)).await;
+                    k * 3
+                }))
+            })
+        }).collect();
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 9);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_dropped_computation_lets_a_waiter_retry() {
+        let cache = AsyncCache::<u32, u32>::new();
+        let c1 = cache.clone();
+        let c2 = cache.clone();
+
+        let out = block_on(async move {
+            let mut first = Box::pin(c1.get(1, |_| async move {
+                sleep(Duration::from_secs(10)).await;
+                1
+            }));
+            // avvia il calcolo e poi lo abbandona
+            std::future::poll_fn(|cx| { let _ = first.as_mut().poll(cx); Poll::Ready(()) }).await;
+            drop(first);
+            *c2.get(1, |_| async move { 2 }).await
+        });
+        assert_eq!(out, 2);
+    }
+}
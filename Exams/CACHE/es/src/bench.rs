@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cache::Cache;
+use crate::sharded::ShardedCache;
+
+// confronto tra la cache con un solo lock e quella a segmenti:
+// `threads` thread leggono `ops` volte chiavi prese da `keys` chiavi distinte,
+// ogni calcolo dura `work`
+pub struct Workload {
+    pub threads: usize,
+    pub ops: usize,
+    pub keys: u64,
+    pub work: Duration,
+}
+
+fn spin(d: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < d {
+        std::hint::spin_loop();
+    }
+}
+
+fn key_for(t: usize, i: usize, keys: u64) -> u64 {
+    // sequenza pseudo-casuale ma ripetibile
+    ((t as u64 + 1) * 2654435761 + i as u64 * 40503) % keys
+}
+
+fn run_with<G>(w: &Workload, get: G) -> Duration where G: Fn(u64) -> u64 + Send + Sync + 'static {
+    let get = Arc::new(get);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..w.threads).map(|t| {
+        let get = Arc::clone(&get);
+        let (ops, keys) = (w.ops, w.keys);
+        thread::spawn(move || {
+            let mut sum = 0u64;
+            for i in 0..ops {
+                sum = sum.wrapping_add(get(key_for(t, i, keys)));
+            }
+            sum
+        })
+    }).collect();
+
+    for h in handles {
+        std::hint::black_box(h.join().unwrap());
+    }
+    start.elapsed()
+}
+
+pub fn single_lock(w: &Workload) -> Duration {
+    let cache = Arc::new(Cache::<u64, u64>::new());
+    let work = w.work;
+    run_with(w, move |k| *cache.get(k, move |k| { spin(work); k * 2 }))
+}
+
+pub fn sharded(w: &Workload, shards: usize) -> Duration {
+    let cache = Arc::new(ShardedCache::<u64, u64>::new(shards));
+    let work = w.work;
+    run_with(w, move |k| *cache.get(k, move |k| { spin(work); k * 2 }))
+}
+
+pub fn run() {
+    let workloads = [
+        Workload { threads: 8, ops: 200_000, keys: 64, work: Duration::from_micros(50) },
+        Workload { threads: 8, ops: 50_000, keys: 10_000, work: Duration::from_micros(20) },
+        Workload { threads: 16, ops: 50_000, keys: 100_000, work: Duration::from_micros(5) },
+    ];
+
+    for w in &workloads {
+        println!("threads={} ops/thread={} keys={} work={:?}", w.threads, w.ops, w.keys, w.work);
+        println!("  single lock : {:?}", single_lock(w));
+        for shards in [4, 16, 64] {
+            println!("  sharded x{:<3}: {:?}", shards, sharded(w, shards));
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+// executor minimale su un solo thread: basta per usare AsyncCache senza dipendenze esterne
+
+struct ThreadWaker {
+    thread: Thread,
+    woken: AtomicBool,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+// esegue il future sul thread corrente, che viene sospeso (park) finché non viene svegliato
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = std::pin::pin!(fut);
+    let tw = Arc::new(ThreadWaker { thread: thread::current(), woken: AtomicBool::new(true) });
+    let waker = Waker::from(Arc::clone(&tw));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if tw.woken.swap(false, Ordering::SeqCst) {
+            if let Poll::Ready(v) = fut.as_mut().poll(&mut cx) {
+                return v;
+            }
+        } else {
+            thread::park();
+        }
+    }
+}
+
+// esegue più future in modo concorrente sullo stesso thread, restituendo i risultati in ordine
+pub fn join_all<F: Future>(futs: Vec<F>) -> Vec<F::Output> {
+    let mut futs: Vec<Pin<Box<F>>> = futs.into_iter().map(Box::pin).collect();
+    let mut out: Vec<Option<F::Output>> = futs.iter().map(|_| None).collect();
+
+    block_on(std::future::poll_fn(|cx| {
+        let mut pending = false;
+        for (i, fut) in futs.iter_mut().enumerate() {
+            if out[i].is_some() {
+                continue;
+            }
+            match fut.as_mut().poll(cx) {
+                Poll::Ready(v) => out[i] = Some(v),
+                Poll::Pending => pending = true,
+            }
+        }
+        if pending { Poll::Pending } else { Poll::Ready(()) }
+    }));
+
+    out.into_iter().map(|v| v.unwrap()).collect()
+}
+
+// attesa non bloccante: un thread di appoggio sveglia il future allo scadere di `d`
+pub fn sleep(d: Duration) -> Sleep {
+    Sleep { d, state: None }
+}
+
+// (scaduto, waker da svegliare)
+type SleepState = Arc<Mutex<(bool, Option<Waker>)>>;
+
+pub struct Sleep {
+    d: Duration,
+    state: Option<SleepState>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        match &self.state {
+            None => {
+                let state = Arc::new(Mutex::new((false, Some(cx.waker().clone()))));
+                let s = Arc::clone(&state);
+                let d = self.d;
+                thread::spawn(move || {
+                    thread::sleep(d);
+                    let mut s = s.lock().unwrap();
+                    s.0 = true;
+                    if let Some(w) = s.1.take() {
+                        w.wake();
+                    }
+                });
+                self.state = Some(state);
+                Poll::Pending
+            },
+            Some(state) => {
+                let mut s = state.lock().unwrap();
+                if s.0 {
+                    Poll::Ready(())
+                } else {
+                    s.1 = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            },
+        }
+    }
+}
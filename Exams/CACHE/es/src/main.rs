@@ -1,47 +1,588 @@
 
 pub mod cache {
-    use std::{collections::HashMap, sync::{Arc, Condvar, Mutex}};
+    use std::{any::Any, collections::HashMap, sync::{Arc, Condvar, Mutex, OnceLock}};
     use std::hash::Hash;
+    use std::thread::{self, JoinHandle};
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+    use crate::snapshot::{self, Persist, SnapshotError};
 
     #[derive(PartialEq)]
     pub enum State<V>{
         Busy,
         Available(Arc<V>)
     }
-    pub struct Cache <K: Clone + Copy + Eq + Hash, V: PartialEq + Clone + Copy> {
-        map: Arc<Mutex<HashMap<K,State<V>>>>,
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Policy {
+        Lru,
+        Lfu,
+        Fifo,
+    }
+
+    // esito di un calcolo in corso, condiviso con i thread che lo stanno aspettando
+    enum Outcome {
+        Done,
+        Failed(Arc<dyn Any + Send + Sync>),
+        Panicked,
+    }
+
+    type Flight = Arc<OnceLock<Outcome>>;
+
+    enum Lookup<V> {
+        Hit(Arc<V>),
+        Compute(Flight),
+        Failed(Arc<dyn Any + Send + Sync>),
+    }
+
+    struct Entry<V> {
+        state: State<V>,
+        flight: Flight,
+        inserted: u64,
+        last_used: u64,
+        uses: u64,
+        expires_at: Option<Instant>,
+        refreshing: bool,
+    }
+
+    impl<V> Entry<V> {
+        fn busy(tick: u64) -> Self {
+            Entry { state: State::Busy, flight: Arc::new(OnceLock::new()), inserted: tick, last_used: tick, uses: 0, expires_at: None, refreshing: false }
+        }
+
+        fn is_expired(&self, now: Instant) -> bool {
+            matches!(self.expires_at, Some(t) if t <= now)
+        }
+    }
+
+    struct Inner<K, V> {
+        map: HashMap<K, Entry<V>>,
+        errors: HashMap<K, (Arc<dyn Any + Send + Sync>, Instant)>,
+        tick: u64,
+        stats: Stats,
+    }
+
+    // istantanea dei contatori della cache
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct Stats {
+        pub hits: u64,
+        pub misses: u64,
+        pub waits: u64,
+        pub compute_time: Duration,
+        pub busy: usize,
+    }
+
+    type EvictCallback<K> = Box<dyn Fn(K) + Send + Sync>;
+
+    struct Reaper {
+        stop: Arc<(Mutex<bool>, Condvar)>,
+        jh: Option<JoinHandle<()>>,
+    }
+
+    impl Drop for Reaper {
+        fn drop(&mut self) {
+            let (lock, cv) = &*self.stop;
+            *lock.lock().unwrap() = true;
+            cv.notify_all();
+            if let Some(jh) = self.jh.take() {
+                jh.join().unwrap();
+            }
+        }
+    }
+
+    pub struct Cache <K: Clone + Eq + Hash, V> {
+        map: Arc<Mutex<Inner<K, V>>>,
         condvar: Arc<Condvar>,
+        capacity: Option<usize>,
+        policy: Policy,
+        ttl: Option<Duration>,
+        error_ttl: Option<Duration>,
+        on_evict: Option<EvictCallback<K>>,
+        reaper: Option<Reaper>,
+    }
+
+    impl<K: Clone + Eq + Hash, V> Default for Cache<K, V> {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
-    impl<K: Clone  + Copy + Eq + Hash, V: PartialEq + Clone + Copy> Cache<K, V> {
+    impl<K: Clone + Eq + Hash, V> Cache<K, V> {
         pub fn new() -> Self {
             Cache {
-                map: Arc::new(Mutex::new(HashMap::new())),
+                map: Arc::new(Mutex::new(Inner { map: HashMap::new(), errors: HashMap::new(), tick: 0, stats: Stats::default() })),
                 condvar: Arc::new(Condvar::new()),
+                capacity: None,
+                policy: Policy::Lru,
+                ttl: None,
+                error_ttl: None,
+                on_evict: None,
+                reaper: None,
             }
         }
 
+        // cache limitata: oltre `capacity` elementi viene scartato un valore Available secondo `policy`
+        pub fn with_capacity(capacity: usize, policy: Policy) -> Self {
+            assert!(capacity > 0, "capacity must be greater than zero");
+            Cache {
+                capacity: Some(capacity),
+                policy,
+                ..Self::new()
+            }
+        }
+
+        // callback invocata (fuori dal lock) per ogni chiave scartata per capacità
+        pub fn on_evict<C>(mut self, callback: C) -> Self where C: Fn(K) + Send + Sync + 'static {
+            self.on_evict = Some(Box::new(callback));
+            self
+        }
+
+        // durata di default dei valori calcolati da get
+        pub fn with_ttl(mut self, ttl: Duration) -> Self {
+            self.ttl = Some(ttl);
+            self
+        }
+
+        // gli errori di try_get vengono restituiti senza ricalcolare per `ttl`
+        pub fn with_error_ttl(mut self, ttl: Duration) -> Self {
+            self.error_ttl = Some(ttl);
+            self
+        }
+
+        pub fn len(&self) -> usize {
+            self.map.lock().unwrap().map.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        pub fn contains(&self, key: &K) -> bool {
+            let now = Instant::now();
+            matches!(self.map.lock().unwrap().map.get(key), Some(e @ Entry { state: State::Available(_), .. }) if !e.is_expired(now))
+        }
+
         pub fn get<F>(&self, key: K, f: F) -> Arc<V> where F: Fn(K) -> V + Send + Sync + 'static {
+            self.get_with_ttl(key, self.ttl, f)
+        }
+
+        // come get, ma il valore eventualmente calcolato scade dopo `ttl` (None = mai)
+        pub fn get_with_ttl<F>(&self, key: K, ttl: Option<Duration>, f: F) -> Arc<V> where F: Fn(K) -> V + Send + Sync + 'static {
+            self.get_ref(key, ttl, &f)
+        }
+
+        // get su più chiavi, i valori sono restituiti nello stesso ordine delle chiavi
+        pub fn get_many<I, F>(&self, keys: I, f: F) -> Vec<Arc<V>>
+        where I: IntoIterator<Item = K>, F: Fn(K) -> V + Send + Sync + 'static {
+            keys.into_iter().map(|k| self.get_ref(k, self.ttl, &f)).collect()
+        }
+
+        fn get_ref<F>(&self, key: K, ttl: Option<Duration>, f: &F) -> Arc<V> where F: Fn(K) -> V {
+            loop {
+                match self.lookup(&key, false) {
+                    Lookup::Hit(val) => return val,
+                    // un errore di try_get non riguarda get: si riprova a calcolare
+                    Lookup::Failed(_) => continue,
+                    Lookup::Compute(flight) => {
+                        // calcolo fuori dal lock; se f va in panic la guardia libera la chiave
+                        let guard = FlightGuard { cache: self, key: &key, flight: &flight, refresh: false, armed: true };
+                        let start = Instant::now();
+                        let val = Arc::new(f(key.clone()));
+                        guard.disarm();
+                        self.publish(key, Arc::clone(&val), ttl, &flight, start.elapsed());
+                        return val;
+                    },
+                }
+            }
+        }
+
+        // versione fallibile di get: l'errore viene condiviso con i thread in attesa ma non memorizzato,
+        // a meno che non sia impostato un ttl per gli errori (with_error_ttl)
+        pub fn try_get<F, E>(&self, key: K, f: F) -> Result<Arc<V>, E>
+        where F: Fn(K) -> Result<V, E> + Send + Sync + 'static, E: Clone + Send + Sync + 'static {
+            let mut negative = true;
+            loop {
+                match self.lookup(&key, negative) {
+                    Lookup::Hit(val) => return Ok(val),
+                    Lookup::Failed(err) => match err.downcast_ref::<E>() {
+                        Some(e) => return Err(e.clone()),
+                        // errore di un try_get con un altro tipo: vale come miss, l'errore memorizzato
+                        // viene scartato e i successivi lookup non lo consultano più
+                        None => {
+                            self.forget_error(&key, &err);
+                            negative = false;
+                        },
+                    },
+                    Lookup::Compute(flight) => {
+                        let guard = FlightGuard { cache: self, key: &key, flight: &flight, refresh: false, armed: true };
+                        let start = Instant::now();
+                        let res = f(key.clone());
+                        guard.disarm();
+                        return match res {
+                            Ok(v) => {
+                                let val = Arc::new(v);
+                                self.publish(key, Arc::clone(&val), self.ttl, &flight, start.elapsed());
+                                Ok(val)
+                            },
+                            Err(e) => {
+                                self.fail(key, Arc::new(e.clone()), &flight, start.elapsed());
+                                Err(e)
+                            },
+                        };
+                    },
+                }
+            }
+        }
+
+        // se il valore è scaduto lo restituisce comunque, mentre un solo chiamante lo ricalcola
+        pub fn get_or_refresh<F>(&self, key: K, f: F) -> Arc<V> where F: Fn(K) -> V + Send + Sync + 'static {
             let mut m = self.map.lock().unwrap();
+            m.tick += 1;
+            let tick = m.tick;
+            let now = Instant::now();
+
+            let stale = match m.map.get_mut(&key) {
+                Some(entry) => match &entry.state {
+                    State::Available(val) => {
+                        entry.last_used = tick;
+                        entry.uses += 1;
+                        if !entry.is_expired(now) || entry.refreshing {
+                            let val = Arc::clone(val);
+                            m.stats.hits += 1;
+                            return val;
+                        }
+                        entry.refreshing = true;
+                        let flight = Arc::clone(&entry.flight);
+                        m.stats.misses += 1;
+                        Some(flight)
+                    },
+                    State::Busy => None,
+                },
+                None => None,
+            };
+            drop(m);
 
-            if let Some(State::Available(val)) = m.get(&key) {
-                return Arc::clone(&val);
+            let Some(flight) = stale else {
+                return self.get(key, f);
+            };
+
+            let guard = FlightGuard { cache: self, key: &key, flight: &flight, refresh: true, armed: true };
+            let start = Instant::now();
+            let val = Arc::new(f(key.clone()));
+            guard.disarm();
+            self.publish(key, Arc::clone(&val), self.ttl, &flight, start.elapsed());
+            val
+        }
+
+        // scarta l'errore in cache solo se è ancora quello letto dal chiamante
+        fn forget_error(&self, key: &K, err: &Arc<dyn Any + Send + Sync>) {
+            let mut m = self.map.lock().unwrap();
+            if m.errors.get(key).is_some_and(|(e, _)| Arc::ptr_eq(e, err)) {
+                m.errors.remove(key);
             }
-            
-            if let Some(State::Busy) = m.get(&key) {
-                m = self.condvar.wait(m).unwrap();
-                return  self.get(key, f);
+        }
+
+        // attende finché la chiave è Busy; se è assente o scaduta la segna Busy e delega il calcolo al chiamante
+        fn lookup(&self, key: &K, negative: bool) -> Lookup<V> {
+            let mut m = self.map.lock().unwrap();
+            loop {
+                let now = Instant::now();
+                if negative {
+                    match m.errors.get(key) {
+                        Some((err, until)) if *until > now => return Lookup::Failed(Arc::clone(err)),
+                        Some(_) => { m.errors.remove(key); },
+                        None => {},
+                    }
+                }
+
+                m.tick += 1;
+                let tick = m.tick;
+                let busy = match m.map.get_mut(key) {
+                    Some(entry) => match &entry.state {
+                        State::Busy => Some(Arc::clone(&entry.flight)),
+                        State::Available(val) if !entry.is_expired(now) => {
+                            entry.last_used = tick;
+                            entry.uses += 1;
+                            let val = Arc::clone(val);
+                            m.stats.hits += 1;
+                            return Lookup::Hit(val);
+                        },
+                        State::Available(_) => None,
+                    },
+                    None => None,
+                };
+
+                match busy {
+                    Some(flight) => {
+                        m.stats.waits += 1;
+                        m = self.condvar.wait_while(m, |_| flight.get().is_none()).unwrap();
+                        if let Some(Outcome::Failed(err)) = flight.get() {
+                            return Lookup::Failed(Arc::clone(err));
+                        }
+                    },
+                    None => {
+                        // chiave assente o scaduta: questo thread calcola il valore
+                        let entry = Entry::busy(tick);
+                        let flight = Arc::clone(&entry.flight);
+                        m.map.insert(key.clone(), entry);
+                        m.stats.misses += 1;
+                        return Lookup::Compute(flight);
+                    },
+                }
             }
-            
-            m.insert(key, State::Busy);
-            let v = f(key.clone());
-            m.insert(key.clone(), State::Available(Arc::new(v)));
+        }
+
+        fn publish(&self, key: K, val: Arc<V>, ttl: Option<Duration>, flight: &Flight, elapsed: Duration) {
+            let mut m = self.map.lock().unwrap();
+            m.stats.compute_time += elapsed;
+            let evicted = self.store(&mut m, key, val, ttl, flight);
+            drop(m);
+            self.finish(evicted);
+        }
+
+        // rende Available il valore di `key` e completa il calcolo `flight`; va chiamata col lock.
+        // Se nel frattempo la chiave è stata sovrascritta (insert) il valore calcolato viene scartato
+        fn store(&self, m: &mut Inner<K, V>, key: K, val: Arc<V>, ttl: Option<Duration>, flight: &Flight) -> Vec<K> {
+            let Some(entry) = m.map.get_mut(&key).filter(|e| Arc::ptr_eq(&e.flight, flight)) else {
+                let _ = flight.set(Outcome::Done);
+                return Vec::new();
+            };
+            entry.state = State::Available(val);
+            entry.uses += 1;
+            entry.expires_at = ttl.map(|d| Instant::now() + d);
+            entry.refreshing = false;
+            entry.flight = Arc::new(OnceLock::new());
+            m.errors.remove(&key);
+            let _ = flight.set(Outcome::Done);
+            self.evict(m, &key)
+        }
+
+        // sveglia chi attende e notifica le chiavi scartate, fuori dal lock
+        fn finish(&self, evicted: Vec<K>) {
+            self.condvar.notify_all();
+            if let Some(callback) = &self.on_evict {
+                for k in evicted {
+                    callback(k);
+                }
+            }
+        }
+
+        fn fail(&self, key: K, err: Arc<dyn Any + Send + Sync>, flight: &Flight, elapsed: Duration) {
+            let mut m = self.map.lock().unwrap();
+            m.stats.compute_time += elapsed;
+            // un insert o un invalidate durante il calcolo rendono l'errore superato: non va ricordato
+            if release(&mut m, &key, flight, false) && let Some(ttl) = self.error_ttl {
+                m.errors.insert(key, (Arc::clone(&err), Instant::now() + ttl));
+            }
+            let _ = flight.set(Outcome::Failed(err));
+            drop(m);
             self.condvar.notify_all();
-            return Arc::new(v);
+        }
+
+        pub fn insert(&self, key: K, value: V) {
+            self.insert_with_ttl(key, value, self.ttl);
+        }
+
+        // se la chiave è in calcolo, chi la sta aspettando riceve subito questo valore
+        pub fn insert_with_ttl(&self, key: K, value: V, ttl: Option<Duration>) {
+            let mut m = self.map.lock().unwrap();
+            m.tick += 1;
+            let tick = m.tick;
+            let entry = m.map.entry(key.clone()).or_insert_with(|| Entry::busy(tick));
+            entry.last_used = tick;
+            let flight = Arc::clone(&entry.flight);
+            let evicted = self.store(&mut m, key, Arc::new(value), ttl, &flight);
+            drop(m);
+            self.finish(evicted);
+        }
+
+        // valore corrente senza calcolarlo e senza aggiornarne l'uso
+        pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+            let now = Instant::now();
+            match self.map.lock().unwrap().map.get(key) {
+                Some(e @ Entry { state: State::Available(val), .. }) if !e.is_expired(now) => Some(Arc::clone(val)),
+                _ => None,
+            }
+        }
+
+        // rimuove il valore di `key` (e l'eventuale errore memorizzato); le chiavi in calcolo non vengono toccate
+        pub fn invalidate(&self, key: &K) -> bool {
+            let mut m = self.map.lock().unwrap();
+            let errored = m.errors.remove(key).is_some();
+            let removed = matches!(m.map.get(key), Some(Entry { state: State::Available(_), .. }));
+            if removed {
+                m.map.remove(key);
+            }
+            removed || errored
+        }
+
+        pub fn invalidate_all(&self) {
+            let mut m = self.map.lock().unwrap();
+            m.map.retain(|_, e| matches!(e.state, State::Busy));
+            m.errors.clear();
+        }
+
+        pub fn stats(&self) -> Stats {
+            let m = self.map.lock().unwrap();
+            Stats {
+                busy: m.map.values().filter(|e| matches!(e.state, State::Busy)).count(),
+                ..m.stats
+            }
+        }
+
+        // rimuove tutti i valori scaduti e restituisce quanti ne ha tolti
+        pub fn purge_expired(&self) -> usize {
+            purge(&mut self.map.lock().unwrap())
+        }
+
+        // sceglie le vittime solo tra i valori Available, mai tra quelli Busy né la chiave appena inserita;
+        // i valori già scaduti vengono scartati per primi
+        fn evict(&self, m: &mut Inner<K, V>, current: &K) -> Vec<K> {
+            let mut evicted = Vec::new();
+            let Some(capacity) = self.capacity else {
+                return evicted;
+            };
+
+            let now = Instant::now();
+            while m.map.len() > capacity {
+                let victim = m.map.iter()
+                    .filter(|(k, e)| *k != current && matches!(e.state, State::Available(_)))
+                    .min_by_key(|(_, e)| {
+                        let (a, b) = match self.policy {
+                            Policy::Lru => (e.last_used, e.inserted),
+                            Policy::Lfu => (e.uses, e.last_used),
+                            Policy::Fifo => (e.inserted, 0),
+                        };
+                        (!e.is_expired(now), a, b)
+                    })
+                    .map(|(k, _)| k.clone());
+
+                match victim {
+                    Some(k) => {
+                        m.map.remove(&k);
+                        evicted.push(k);
+                    },
+                    None => break,
+                }
+            }
+            evicted
+        }
+    }
+
+    impl<K: Clone + Eq + Hash + Persist, V: Persist> Cache<K, V> {
+        // salva su file i valori Available non scaduti; restituisce quante voci sono state scritte
+        pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<usize, SnapshotError> {
+            let now = Instant::now();
+            let entries: Vec<(K, Arc<V>)> = {
+                let m = self.map.lock().unwrap();
+                m.map.iter()
+                    .filter(|(_, e)| !e.is_expired(now))
+                    .filter_map(|(k, e)| match &e.state {
+                        State::Available(v) => Some((k.clone(), Arc::clone(v))),
+                        State::Busy => None,
+                    })
+                    .collect()
+            };
+            snapshot::write(path.as_ref(), entries.iter().map(|(k, v)| (k, &**v)))
+        }
+
+        // carica uno snapshot come valori Available (con il ttl di default della cache);
+        // un file corrotto o di un'altra versione non modifica la cache
+        pub fn load_from<P: AsRef<Path>>(&self, path: P) -> Result<usize, SnapshotError> {
+            let entries = snapshot::read::<K, V>(path.as_ref())?;
+            let n = entries.len();
+            for (k, v) in entries {
+                self.insert(k, v);
+            }
+            Ok(n)
         }
     }
+
+    impl<K: Clone + Eq + Hash + Send + 'static, V: Send + Sync + 'static> Cache<K, V> {
+        // avvia un thread che ogni `interval` rimuove i valori scaduti; si ferma al drop della cache
+        pub fn with_reaper(mut self, interval: Duration) -> Self {
+            let stop = Arc::new((Mutex::new(false), Condvar::new()));
+            let stop_c = Arc::clone(&stop);
+            let map = Arc::clone(&self.map);
+
+            let jh = thread::spawn(move || {
+                let (lock, cv) = &*stop_c;
+                let mut stopped = lock.lock().unwrap();
+                loop {
+                    let (s, _) = cv.wait_timeout_while(stopped, interval, |s| !*s).unwrap();
+                    stopped = s;
+                    if *stopped {
+                        break;
+                    }
+                    purge(&mut map.lock().unwrap());
+                }
+            });
+
+            self.reaper = Some(Reaper { stop, jh: Some(jh) });
+            self
+        }
+    }
+
+    // se il calcolo va in panic rimette la chiave nello stato precedente e sveglia chi attende
+    struct FlightGuard<'a, K: Clone + Eq + Hash, V> {
+        cache: &'a Cache<K, V>,
+        key: &'a K,
+        flight: &'a Flight,
+        refresh: bool,
+        armed: bool,
+    }
+
+    impl<K: Clone + Eq + Hash, V> FlightGuard<'_, K, V> {
+        fn disarm(mut self) {
+            self.armed = false;
+        }
+    }
+
+    impl<K: Clone + Eq + Hash, V> Drop for FlightGuard<'_, K, V> {
+        fn drop(&mut self) {
+            if !self.armed {
+                return;
+            }
+            // il mutex può essere avvelenato se il panic è avvenuto mentre era acquisito
+            let mut m = self.cache.map.lock().unwrap_or_else(|e| e.into_inner());
+            release(&mut m, self.key, self.flight, self.refresh);
+            let _ = self.flight.set(Outcome::Panicked);
+            drop(m);
+            self.cache.condvar.notify_all();
+        }
+    }
+
+    // toglie la chiave Busy del calcolo `flight` (o annulla il refresh) senza toccare calcoli più recenti;
+    // restituisce false se la chiave non appartiene più a `flight`
+    fn release<K: Clone + Eq + Hash, V>(m: &mut Inner<K, V>, key: &K, flight: &Flight, refresh: bool) -> bool {
+        let Some(entry) = m.map.get_mut(key) else {
+            return false;
+        };
+        if !Arc::ptr_eq(&entry.flight, flight) {
+            return false;
+        }
+        if refresh {
+            entry.refreshing = false;
+        } else if matches!(entry.state, State::Busy) {
+            m.map.remove(key);
+        }
+        true
+    }
+
+    // i valori in fase di refresh restano, così get_or_refresh può continuare a servirli
+    fn purge<K: Clone + Eq + Hash, V>(m: &mut Inner<K, V>) -> usize {
+        let now = Instant::now();
+        let before = m.map.len();
+        m.map.retain(|_, e| e.refreshing || !e.is_expired(now));
+        before - m.map.len()
+    }
 }
 
+pub mod sharded;
+pub mod async_cache;
+pub mod executor;
+pub mod snapshot;
+mod bench;
 
 use std::sync::Arc;
 use std::thread;
@@ -52,6 +593,12 @@ use std::time::Duration;
 use cache::Cache;
 
 fn main() {
+    // `cargo run --release -- bench` confronta la cache con un solo lock e quella a segmenti
+    if std::env::args().nth(1).as_deref() == Some("bench") {
+        bench::run();
+        return;
+    }
+
     let cache = Arc::new(Cache::<u32, u64>::new());
 
     // Funzione costosa (simulate con sleep + stampa)
@@ -81,6 +628,422 @@ fn main() {
     println!("Tutti i thread hanno terminato.");
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cache::Policy;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_compute_once_with_waiters() {
+        let cache = Arc::new(Cache::<u32, u64>::with_capacity(4, Policy::Lru));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for _ in 0..8 {
+            let c = Arc::clone(&cache);
+            let calls = Arc::clone(&calls);
+            handles.push(thread::spawn(move || {
+                *c.get(7, move |k| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(50));
+                    k as u64 * 10
+                })
+            }));
+        }
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 70);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let ev = Arc::clone(&evicted);
+        let cache = Cache::<u32, u32>::with_capacity(2, Policy::Lru)
+            .on_evict(move |k| ev.lock().unwrap().push(k));
+
+        cache.get(1, |k| k);
+        cache.get(2, |k| k);
+        cache.get(1, |k| k);
+        cache.get(3, |k| k);
+
+        assert_eq!(*evicted.lock().unwrap(), vec![2]);
+        assert!(cache.contains(&1));
+        assert!(cache.contains(&3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_lfu_evicts_least_frequently_used() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let ev = Arc::clone(&evicted);
+        let cache = Cache::<u32, u32>::with_capacity(2, Policy::Lfu)
+            .on_evict(move |k| ev.lock().unwrap().push(k));
+
+        cache.get(1, |k| k);
+        cache.get(1, |k| k);
+        cache.get(2, |k| k);
+        cache.get(2, |k| k);
+        cache.get(2, |k| k);
+        cache.get(3, |k| k);
+
+        assert_eq!(*evicted.lock().unwrap(), vec![1]);
+        assert!(cache.contains(&2));
+    }
+
+    #[test]
+    fn test_fifo_evicts_oldest_insert() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let ev = Arc::clone(&evicted);
+        let cache = Cache::<u32, u32>::with_capacity(2, Policy::Fifo)
+            .on_evict(move |k| ev.lock().unwrap().push(k));
+
+        cache.get(1, |k| k);
+        cache.get(2, |k| k);
+        cache.get(1, |k| k);
+        cache.get(3, |k| k);
+
+        assert_eq!(*evicted.lock().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_busy_entries_are_never_evicted() {
+        let cache = Arc::new(Cache::<u32, u32>::with_capacity(1, Policy::Lru));
+        let c = Arc::clone(&cache);
+        let slow = thread::spawn(move || {
+            *c.get(1, |k| {
+                thread::sleep(Duration::from_millis(200));
+                k
+            })
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        cache.get(2, |k| k);
+        assert_eq!(slow.join().unwrap(), 1);
+        assert!(cache.contains(&1));
+        assert!(!cache.contains(&2));
+    }
+
+    #[test]
+    fn test_expired_value_is_recomputed() {
+        let cache = Cache::<u32, u32>::new().with_ttl(Duration::from_millis(50));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..2 {
+            let calls = Arc::clone(&calls);
+            cache.get(1, move |k| { calls.fetch_add(1, Ordering::SeqCst); k });
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        thread::sleep(Duration::from_millis(80));
+        assert!(!cache.contains(&1));
+        let c = Arc::clone(&calls);
+        cache.get(1, move |k| { c.fetch_add(1, Ordering::SeqCst); k });
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_ttl_per_insert_overrides_default() {
+        let cache = Cache::<u32, u32>::new().with_ttl(Duration::from_millis(30));
+        cache.get_with_ttl(1, None, |k| k);
+        cache.get(2, |k| k);
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(cache.contains(&1));
+        assert!(!cache.contains(&2));
+        assert_eq!(cache.purge_expired(), 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_reaper_sweeps_expired_keys() {
+        let cache = Cache::<u32, u32>::new()
+            .with_ttl(Duration::from_millis(20))
+            .with_reaper(Duration::from_millis(10));
+        cache.get(1, |k| k);
+        cache.get(2, |k| k);
+        assert_eq!(cache.len(), 2);
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_get_or_refresh_serves_stale_while_one_recomputes() {
+        let cache = Arc::new(Cache::<u32, u32>::new().with_ttl(Duration::from_millis(20)));
+        cache.get(1, |_| 1);
+        thread::sleep(Duration::from_millis(40));
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = Arc::clone(&cache);
+        let cl = Arc::clone(&calls);
+        let refresher = thread::spawn(move || {
+            *c.get_or_refresh(1, move |_| {
+                cl.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(100));
+                2
+            })
+        });
+
+        thread::sleep(Duration::from_millis(30));
+        let mut readers = vec![];
+        for _ in 0..4 {
+            let c = Arc::clone(&cache);
+            let cl = Arc::clone(&calls);
+            readers.push(thread::spawn(move || {
+                *c.get_or_refresh(1, move |_| { cl.fetch_add(1, Ordering::SeqCst); 3 })
+            }));
+        }
+        for r in readers {
+            assert_eq!(r.join().unwrap(), 1);
+        }
+
+        assert_eq!(refresher.join().unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(*cache.get_or_refresh(1, |_| 4), 2);
+    }
+
+    #[test]
+    fn test_try_get_shares_error_with_waiters() {
+        let cache = Arc::new(Cache::<u32, u32>::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for _ in 0..4 {
+            let c = Arc::clone(&cache);
+            let calls = Arc::clone(&calls);
+            handles.push(thread::spawn(move || {
+                c.try_get(1, move |_| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(100));
+                    Err::<u32, String>("boom".to_string())
+                })
+            }));
+        }
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), Err("boom".to_string()));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // l'errore non viene memorizzato
+        assert_eq!(cache.try_get(1, |k| Ok::<u32, String>(k * 2)).map(|v| *v), Ok(2));
+    }
+
+    #[test]
+    fn test_negative_cache_ttl() {
+        let cache = Cache::<u32, u32>::new().with_error_ttl(Duration::from_millis(50));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..3 {
+            let calls = Arc::clone(&calls);
+            let res = cache.try_get(1, move |_| { calls.fetch_add(1, Ordering::SeqCst); Err::<u32, i32>(-1) });
+            assert_eq!(res, Err(-1));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        thread::sleep(Duration::from_millis(80));
+        assert_eq!(cache.try_get(1, |k| Ok::<u32, i32>(k + 1)).map(|v| *v), Ok(2));
+    }
+
+    #[test]
+    fn test_negative_cache_with_other_error_type_is_a_miss() {
+        let cache = Cache::<u32, u32>::new().with_error_ttl(Duration::from_secs(60));
+        assert_eq!(cache.try_get(1, |_| Err::<u32, i32>(-1)), Err(-1));
+
+        // senza aspettare il ttl dell'errore memorizzato
+        let start = Instant::now();
+        assert_eq!(cache.try_get(1, |k| Err::<u32, String>(format!("no {k}"))), Err("no 1".to_string()));
+        assert_eq!(cache.try_get(1, |k| Ok::<u32, i32>(k + 1)).map(|v| *v), Ok(2));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_failure_superseded_by_insert_is_not_cached() {
+        let cache = Arc::new(Cache::<u32, u32>::new().with_error_ttl(Duration::from_secs(60)));
+        let c = Arc::clone(&cache);
+        // il valore inserito durante il calcolo vince sull'errore del calcolo stesso
+        assert_eq!(cache.try_get(1, move |k| { c.insert(k, 7); Err::<u32, i32>(-1) }), Err(-1));
+        assert_eq!(cache.try_get(1, |_| Err::<u32, i32>(-2)).map(|v| *v), Ok(7));
+    }
+
+    #[test]
+    fn test_panic_releases_busy_key_and_wakes_waiters() {
+        let cache = Arc::new(Cache::<u32, u32>::new());
+        let c = Arc::clone(&cache);
+        let panicking = thread::spawn(move || {
+            c.get(1, |_| {
+                thread::sleep(Duration::from_millis(100));
+                panic!("compute failed");
+            });
+        });
+
+        thread::sleep(Duration::from_millis(30));
+        let c = Arc::clone(&cache);
+        let waiter = thread::spawn(move || *c.get(1, |k| k + 10));
+
+        assert!(panicking.join().is_err());
+        assert_eq!(waiter.join().unwrap(), 11);
+        assert!(cache.contains(&1));
+    }
+
+    #[test]
+    fn test_panic_during_refresh_keeps_stale_value() {
+        let cache = Arc::new(Cache::<u32, u32>::new().with_ttl(Duration::from_millis(10)));
+        cache.get(1, |_| 1);
+        thread::sleep(Duration::from_millis(30));
+
+        // refresh lento: finché `gate` non lo sblocca chi legge deve ricevere il valore scaduto
+        let refresh = |fail: bool| {
+            let (started_tx, started) = std::sync::mpsc::channel();
+            let (release, gate) = std::sync::mpsc::channel::<()>();
+            let gate = Mutex::new(gate);
+            let c = Arc::clone(&cache);
+            let h = thread::spawn(move || *c.get_or_refresh(1, move |_| {
+                started_tx.send(()).unwrap();
+                gate.lock().unwrap().recv().unwrap();
+                if fail {
+                    panic!("refresh failed");
+                }
+                2
+            }));
+            started.recv().unwrap();
+            (h, release)
+        };
+
+        let (h, release) = refresh(true);
+        assert_eq!(*cache.get_or_refresh(1, |_| 99), 1);
+        release.send(()).unwrap();
+        assert!(h.join().is_err());
+
+        // dopo il panic il valore scaduto è ancora in cache e viene servito durante il nuovo refresh
+        let (h, release) = refresh(false);
+        assert_eq!(*cache.get_or_refresh(1, |_| 99), 1);
+        release.send(()).unwrap();
+        assert_eq!(h.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_insert_peek_and_invalidate() {
+        let cache = Cache::<u32, u32>::new();
+        assert_eq!(cache.peek(&1), None);
+
+        cache.insert(1, 100);
+        assert_eq!(cache.peek(&1).map(|v| *v), Some(100));
+        assert_eq!(*cache.get(1, |k| k), 100);
+
+        assert!(cache.invalidate(&1));
+        assert!(!cache.invalidate(&1));
+        assert_eq!(*cache.get(1, |k| k), 1);
+
+        cache.insert(2, 2);
+        cache.invalidate_all();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_insert_completes_pending_computation() {
+        let cache = Arc::new(Cache::<u32, u32>::new());
+        let c = Arc::clone(&cache);
+        let slow = thread::spawn(move || {
+            *c.get(1, |_| {
+                thread::sleep(Duration::from_millis(300));
+                1
+            })
+        });
+
+        thread::sleep(Duration::from_millis(30));
+        let c = Arc::clone(&cache);
+        let waiter = thread::spawn(move || *c.get(1, |_| 3));
+        thread::sleep(Duration::from_millis(30));
+
+        cache.insert(1, 2);
+        assert_eq!(waiter.join().unwrap(), 2);
+        assert_eq!(slow.join().unwrap(), 1);
+        assert_eq!(*cache.peek(&1).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_get_many_keeps_key_order() {
+        let cache = Cache::<u32, u32>::new();
+        cache.insert(2, 20);
+        let vals: Vec<u32> = cache.get_many(vec![3, 2, 1], |k| k).into_iter().map(|v| *v).collect();
+        assert_eq!(vals, vec![3, 20, 1]);
+    }
+
+    #[test]
+    fn test_stats_snapshot() {
+        let cache = Arc::new(Cache::<u32, u32>::new());
+        cache.get(1, |k| { thread::sleep(Duration::from_millis(20)); k });
+        cache.get(1, |k| k);
+        cache.get(2, |k| k);
+
+        let c = Arc::clone(&cache);
+        let slow = thread::spawn(move || {
+            *c.get(3, |k| {
+                thread::sleep(Duration::from_millis(200));
+                k
+            })
+        });
+        thread::sleep(Duration::from_millis(30));
+        let c = Arc::clone(&cache);
+        let waiter = thread::spawn(move || *c.get(3, |k| k));
+        thread::sleep(Duration::from_millis(30));
+
+        let stats = cache.stats();
+        assert_eq!(stats.busy, 1);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.waits, 1);
+
+        slow.join().unwrap();
+        waiter.join().unwrap();
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.busy, 0);
+        assert!(stats.compute_time >= Duration::from_millis(220));
+    }
+
+    #[test]
+    fn test_save_and_load_snapshot() {
+        let path = std::env::temp_dir().join(format!("cache-main-{}.bin", std::process::id()));
+        let cache = Cache::<u32, String>::new();
+        cache.get(1, |k| format!("v{}", k));
+        cache.get(2, |k| format!("v{}", k));
+        assert_eq!(cache.save_to(&path).unwrap(), 2);
+
+        let warm = Cache::<u32, String>::new();
+        assert_eq!(warm.load_from(&path).unwrap(), 2);
+        assert_eq!(*warm.get(1, |_| unreachable!()), "v1");
+        assert_eq!(warm.peek(&2).as_deref().map(String::as_str), Some("v2"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_corrupted_snapshot_leaves_cache_untouched() {
+        use snapshot::SnapshotError;
+
+        let path = std::env::temp_dir().join(format!("cache-main-bad-{}.bin", std::process::id()));
+        let cache = Cache::<u32, u32>::new();
+        cache.insert(1, 1);
+        cache.save_to(&path).unwrap();
+
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        std::fs::write(&path, &data).unwrap();
+
+        let other = Cache::<u32, u32>::new();
+        assert!(matches!(other.load_from(&path), Err(SnapshotError::ChecksumMismatch { .. })));
+        assert!(other.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
 
 /*
 impl<K: Clone + Copy + Eq + Hash, V: Clone + Copy> Cache<K, V> {
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Condvar, Mutex};
+
+// attesa dedicata a una singola chiave: chi aspetta viene svegliato solo quando
+// termina il calcolo della propria chiave
+struct Slot<V> {
+    value: Mutex<SlotState<V>>,
+    condvar: Condvar,
+}
+
+enum SlotState<V> {
+    Pending,
+    Ready(Arc<V>),
+    Abandoned,
+}
+
+enum Entry<V> {
+    Busy(Arc<Slot<V>>),
+    Available(Arc<V>),
+}
+
+pub struct ShardedCache<K: Clone + Eq + Hash, V> {
+    shards: Vec<Mutex<HashMap<K, Entry<V>>>>,
+    hasher: RandomState,
+}
+
+impl<K: Clone + Eq + Hash, V> ShardedCache<K, V> {
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "shards must be greater than zero");
+        ShardedCache {
+            shards: (0..shards).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<HashMap<K, Entry<V>>> {
+        let i = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[i]
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get<F>(&self, key: K, f: F) -> Arc<V> where F: Fn(K) -> V + Send + Sync + 'static {
+        let shard = self.shard(&key);
+        loop {
+            let mut m = shard.lock().unwrap();
+            let slot = match m.get(&key) {
+                Some(Entry::Available(val)) => return Arc::clone(val),
+                Some(Entry::Busy(slot)) => Arc::clone(slot),
+                None => {
+                    let slot = Arc::new(Slot { value: Mutex::new(SlotState::Pending), condvar: Condvar::new() });
+                    m.insert(key.clone(), Entry::Busy(Arc::clone(&slot)));
+                    drop(m);
+                    return self.compute(shard, key, slot, f);
+                }
+            };
+            // il lock del segmento viene rilasciato: l'attesa avviene solo sullo slot della chiave
+            drop(m);
+
+            let mut state = slot.value.lock().unwrap();
+            state = slot.condvar.wait_while(state, |s| matches!(s, SlotState::Pending)).unwrap();
+            if let SlotState::Ready(val) = &*state {
+                return Arc::clone(val);
+            }
+            // il calcolo è andato in panic: si riprova
+        }
+    }
+
+    fn compute<F>(&self, shard: &Mutex<HashMap<K, Entry<V>>>, key: K, slot: Arc<Slot<V>>, f: F) -> Arc<V>
+    where F: Fn(K) -> V {
+        let guard = SlotGuard { shard, key: &key, slot: &slot, armed: true };
+        let val = Arc::new(f(key.clone()));
+        guard.disarm();
+
+        shard.lock().unwrap().insert(key, Entry::Available(Arc::clone(&val)));
+        *slot.value.lock().unwrap() = SlotState::Ready(Arc::clone(&val));
+        slot.condvar.notify_all();
+        val
+    }
+}
+
+// se f va in panic libera la chiave e sveglia chi attende su quello slot
+struct SlotGuard<'a, K: Eq + Hash, V> {
+    shard: &'a Mutex<HashMap<K, Entry<V>>>,
+    key: &'a K,
+    slot: &'a Arc<Slot<V>>,
+    armed: bool,
+}
+
+impl<K: Eq + Hash, V> SlotGuard<'_, K, V> {
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for SlotGuard<'_, K, V> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let mut m = self.shard.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(m.get(self.key), Some(Entry::Busy(s)) if Arc::ptr_eq(s, self.slot)) {
+            m.remove(self.key);
+        }
+        drop(m);
+        *self.slot.value.lock().unwrap_or_else(|e| e.into_inner()) = SlotState::Abandoned;
+        self.slot.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_compute_once_per_key() {
+        let cache = Arc::new(ShardedCache::<u32, u64>::new(8));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for i in 0..16 {
+            let c = Arc::clone(&cache);
+            let calls = Arc::clone(&calls);
+            handles.push(thread::spawn(move || {
+                *c.get(i % 4, move |k| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(50));
+                    k as u64 * 10
+                })
+            }));
+        }
+
+        for (i, h) in handles.into_iter().enumerate() {
+            assert_eq!(h.join().unwrap(), (i as u64 % 4) * 10);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn test_slow_key_does_not_block_other_keys() {
+        let cache = Arc::new(ShardedCache::<u32, u32>::new(1));
+        let c = Arc::clone(&cache);
+        let slow = thread::spawn(move || {
+            *c.get(1, |k| {
+                thread::sleep(Duration::from_millis(300));
+                k
+            })
+        });
+
+        thread::sleep(Duration::from_millis(30));
+        // anche con un solo segmento la chiave 2 non aspetta il calcolo della chiave 1
+        let start = Instant::now();
+        assert_eq!(*cache.get(2, |k| k), 2);
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(slow.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_panic_wakes_waiters_of_the_same_key() {
+        let cache = Arc::new(ShardedCache::<u32, u32>::new(4));
+        let c = Arc::clone(&cache);
+        let panicking = thread::spawn(move || {
+            c.get(1, |_| {
+                thread::sleep(Duration::from_millis(100));
+                panic!("compute failed");
+            });
+        });
+
+        thread::sleep(Duration::from_millis(30));
+        let c = Arc::clone(&cache);
+        let waiter = thread::spawn(move || *c.get(1, |k| k + 1));
+
+        assert!(panicking.join().is_err());
+        assert_eq!(waiter.join().unwrap(), 2);
+    }
+}
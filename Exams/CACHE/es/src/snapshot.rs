@@ -0,0 +1,256 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// formato del file:
+//   "MCSN" | versione u16 | numero di voci u64 | voci (chiave, valore) | crc32 u32
+// tutti gli interi sono little endian, il crc copre tutti i byte che lo precedono
+const MAGIC: &[u8; 4] = b"MCSN";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch { expected: u32, found: u32 },
+    Truncated,
+    InvalidData(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "i/o error: {}", e),
+            SnapshotError::BadMagic => write!(f, "not a cache snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {} (expected {})", v, VERSION),
+            SnapshotError::ChecksumMismatch { expected, found } => write!(f, "checksum mismatch: expected {:08x}, found {:08x}", expected, found),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::InvalidData(what) => write!(f, "invalid data: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+// tipi che possono essere scritti nello snapshot
+pub trait Persist: Sized {
+    fn write_to(&self, out: &mut Vec<u8>);
+    fn read_from(input: &mut &[u8]) -> Result<Self, SnapshotError>;
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], SnapshotError> {
+    if input.len() < n {
+        return Err(SnapshotError::Truncated);
+    }
+    let (head, tail) = input.split_at(n);
+    *input = tail;
+    Ok(head)
+}
+
+macro_rules! persist_int {
+    ($($t:ty),*) => {$(
+        impl Persist for $t {
+            fn write_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn read_from(input: &mut &[u8]) -> Result<Self, SnapshotError> {
+                let bytes = take(input, std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+persist_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Persist for usize {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        (*self as u64).write_to(out);
+    }
+
+    fn read_from(input: &mut &[u8]) -> Result<Self, SnapshotError> {
+        usize::try_from(u64::read_from(input)?).map_err(|_| SnapshotError::InvalidData("usize out of range"))
+    }
+}
+
+impl Persist for bool {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn read_from(input: &mut &[u8]) -> Result<Self, SnapshotError> {
+        match u8::read_from(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidData("bool")),
+        }
+    }
+}
+
+impl Persist for String {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        self.len().write_to(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn read_from(input: &mut &[u8]) -> Result<Self, SnapshotError> {
+        let len = usize::read_from(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| SnapshotError::InvalidData("string is not utf-8"))
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        self.len().write_to(out);
+        for item in self {
+            item.write_to(out);
+        }
+    }
+
+    fn read_from(input: &mut &[u8]) -> Result<Self, SnapshotError> {
+        let len = usize::read_from(input)?;
+        // ogni elemento occupa almeno un byte: evita allocazioni enormi con lunghezze corrotte
+        if len > input.len() {
+            return Err(SnapshotError::Truncated);
+        }
+        (0..len).map(|_| T::read_from(input)).collect()
+    }
+}
+
+impl<A: Persist, B: Persist> Persist for (A, B) {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        self.0.write_to(out);
+        self.1.write_to(out);
+    }
+
+    fn read_from(input: &mut &[u8]) -> Result<Self, SnapshotError> {
+        Ok((A::read_from(input)?, B::read_from(input)?))
+    }
+}
+
+// crc32 IEEE (polinomio riflesso 0xEDB88320), calcolato bit per bit
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn write<'a, K, V, I>(path: &Path, entries: I) -> Result<usize, SnapshotError>
+where K: Persist + 'a, V: Persist + 'a, I: IntoIterator<Item = (&'a K, &'a V)> {
+    let mut body = Vec::new();
+    let mut count = 0u64;
+    for (k, v) in entries {
+        k.write_to(&mut body);
+        v.write_to(&mut body);
+        count += 1;
+    }
+
+    let mut out = Vec::with_capacity(body.len() + 18);
+    out.extend_from_slice(MAGIC);
+    VERSION.write_to(&mut out);
+    count.write_to(&mut out);
+    out.extend_from_slice(&body);
+    crc32(&out).write_to(&mut out);
+
+    // scrittura su file temporaneo e rename, così un crash non lascia uno snapshot a metà
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, &out)?;
+    fs::rename(&tmp, path)?;
+    Ok(count as usize)
+}
+
+pub fn read<K: Persist, V: Persist>(path: &Path) -> Result<Vec<(K, V)>, SnapshotError> {
+    let data = fs::read(path)?;
+    if !data.starts_with(MAGIC) {
+        return Err(if MAGIC.starts_with(&data) { SnapshotError::Truncated } else { SnapshotError::BadMagic });
+    }
+    if data.len() < MAGIC.len() + 2 + 8 + 4 {
+        return Err(SnapshotError::Truncated);
+    }
+
+    let (content, tail) = data.split_at(data.len() - 4);
+    let mut input = &content[4..];
+    let version = u16::read_from(&mut input)?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let expected = u32::from_le_bytes(tail.try_into().unwrap());
+    let found = crc32(content);
+    if expected != found {
+        return Err(SnapshotError::ChecksumMismatch { expected, found });
+    }
+
+    let count = u64::read_from(&mut input)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let k = K::read_from(&mut input)?;
+        let v = V::read_from(&mut input)?;
+        entries.push((k, v));
+    }
+    if !input.is_empty() {
+        return Err(SnapshotError::InvalidData("trailing bytes after last entry"));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tmp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("cache-snapshot-{}-{}.bin", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_crc32_known_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let path = tmp_path("roundtrip");
+        let entries = vec![(1u32, "uno".to_string()), (2, "due".to_string())];
+        assert_eq!(write(&path, entries.iter().map(|(k, v)| (k, v))).unwrap(), 2);
+        assert_eq!(read::<u32, String>(&path).unwrap(), entries);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corruption_is_detected() {
+        let path = tmp_path("corrupted");
+        write(&path, [(&1u64, &10u64)]).unwrap();
+
+        let mut data = fs::read(&path).unwrap();
+        let i = data.len() - 6;
+        data[i] ^= 0xFF;
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(read::<u64, u64>(&path), Err(SnapshotError::ChecksumMismatch { .. })));
+
+        data[4] = 9;
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(read::<u64, u64>(&path), Err(SnapshotError::UnsupportedVersion(9))));
+
+        fs::write(&path, b"garbage-garbage-garbage").unwrap();
+        assert!(matches!(read::<u64, u64>(&path), Err(SnapshotError::BadMagic)));
+
+        fs::write(&path, b"MCSN").unwrap();
+        assert!(matches!(read::<u64, u64>(&path), Err(SnapshotError::Truncated)));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,103 +1,744 @@
+pub mod clock {
+    use std::sync::{Arc, Mutex, Weak};
+    use std::time::{Duration, Instant, SystemTime};
 
+    pub type Listener = Arc<dyn Fn() + Send + Sync>;
+
+    // sorgente del tempo per code e timer: nei test si usa MockClock e si fa avanzare il tempo a mano
+    pub trait Clock: Send + Sync {
+        fn now(&self) -> Instant;
+
+        // quanto attendere davvero sulla condvar per arrivare a `deadline`;
+        // None = attendere finché non arriva una notifica
+        fn wait_for(&self, deadline: Instant) -> Option<Duration>;
+
+        // ora di calendario corrispondente a now(), serve per le espressioni cron
+        fn system_time(&self) -> SystemTime;
+
+        // `f` viene chiamata ogni volta che il tempo avanza senza che passi tempo reale;
+        // il clock la tiene con un riferimento debole, quindi resta attiva finché il chiamante tiene `f`
+        fn on_advance(&self, _f: &Listener) {}
+    }
+
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn wait_for(&self, deadline: Instant) -> Option<Duration> {
+            Some(deadline.saturating_duration_since(Instant::now()))
+        }
+
+        fn system_time(&self) -> SystemTime {
+            SystemTime::now()
+        }
+    }
+
+    pub struct MockClock {
+        start: (Instant, SystemTime),
+        now: Mutex<Instant>,
+        listeners: Mutex<Vec<Weak<dyn Fn() + Send + Sync>>>,
+    }
+
+    impl MockClock {
+        pub fn new() -> Arc<Self> {
+            Self::at(SystemTime::now())
+        }
+
+        // orologio finto che parte dall'ora di calendario `wall`
+        pub fn at(wall: SystemTime) -> Arc<Self> {
+            let now = Instant::now();
+            Arc::new(MockClock { start: (now, wall), now: Mutex::new(now), listeners: Mutex::new(Vec::new()) })
+        }
+
+        pub fn advance(&self, d: Duration) {
+            *self.now.lock().unwrap() += d;
+            // i listener di code ed executor già distrutti vengono tolti
+            let listeners: Vec<Listener> = {
+                let mut l = self.listeners.lock().unwrap();
+                l.retain(|f| f.strong_count() > 0);
+                l.iter().filter_map(Weak::upgrade).collect()
+            };
+            for f in listeners {
+                f();
+            }
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn wait_for(&self, _deadline: Instant) -> Option<Duration> {
+            None
+        }
+
+        fn system_time(&self) -> SystemTime {
+            self.start.1 + (self.now() - self.start.0)
+        }
+
+        fn on_advance(&self, f: &Listener) {
+            self.listeners.lock().unwrap().push(Arc::downgrade(f));
+        }
+    }
+}
+
+pub mod cron {
+    use std::fmt;
+
+    // espressione "minuti ore giorni" (giorno del mese, UTC); ogni campo accetta
+    // `*`, un numero, liste `a,b`, intervalli `a-b` e passi `*/n` o `a-b/n`
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CronExpr {
+        minutes: u64,
+        hours: u64,
+        days: u64,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum CronError {
+        FieldCount(usize),
+        Invalid(String),
+        OutOfRange { field: &'static str, value: u32 },
+    }
+
+    impl fmt::Display for CronError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                CronError::FieldCount(n) => write!(f, "expected 3 fields (minute hour day), found {}", n),
+                CronError::Invalid(s) => write!(f, "invalid field '{}'", s),
+                CronError::OutOfRange { field, value } => write!(f, "{} out of range: {}", field, value),
+            }
+        }
+    }
+
+    impl std::error::Error for CronError {}
+
+    fn parse_field(s: &str, name: &'static str, min: u32, max: u32) -> Result<u64, CronError> {
+        let invalid = || CronError::Invalid(s.to_string());
+        let number = |v: &str| -> Result<u32, CronError> {
+            let n: u32 = v.parse().map_err(|_| invalid())?;
+            if n < min || n > max {
+                return Err(CronError::OutOfRange { field: name, value: n });
+            }
+            Ok(n)
+        };
+
+        let mut bits = 0u64;
+        for part in s.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((r, st)) => (r, st.parse::<u32>().map_err(|_| invalid()).and_then(|n| if n == 0 { Err(invalid()) } else { Ok(n) })?),
+                None => (part, 1),
+            };
+            let (lo, hi) = match range {
+                "*" => (min, max),
+                r => match r.split_once('-') {
+                    Some((a, b)) => (number(a)?, number(b)?),
+                    // "5/10" significa da 5 in poi ogni 10
+                    None if step > 1 => (number(r)?, max),
+                    None => (number(r)?, number(r)?),
+                },
+            };
+            if lo > hi {
+                return Err(invalid());
+            }
+            for v in (lo..=hi).step_by(step as usize) {
+                bits |= 1 << v;
+            }
+        }
+        Ok(bits)
+    }
+
+    // (anno, mese, giorno) dal numero di giorni dal 1970-01-01
+    fn civil_from_days(z: i64) -> (i64, u32, u32) {
+        let z = z + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        (yoe + era * 400 + (m <= 2) as i64, m, d)
+    }
+
+    impl CronExpr {
+        pub fn parse(s: &str) -> Result<Self, CronError> {
+            let fields: Vec<&str> = s.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(CronError::FieldCount(fields.len()));
+            }
+            Ok(CronExpr {
+                minutes: parse_field(fields[0], "minute", 0, 59)?,
+                hours: parse_field(fields[1], "hour", 0, 23)?,
+                days: parse_field(fields[2], "day", 1, 31)?,
+            })
+        }
+
+        // primo istante (secondi unix, minuto intero) strettamente successivo a `after`
+        pub fn next_after(&self, after: u64) -> Option<u64> {
+            let mut t = after / 60 + 1;
+            // ogni giorno del mese esiste almeno una volta ogni 4 anni: il ciclo è comunque limitato
+            for _ in 0..200_000 {
+                let day = t / 1440;
+                let (_, _, d) = civil_from_days(day as i64);
+                if self.days & (1 << d) == 0 {
+                    t = (day + 1) * 1440;
+                    continue;
+                }
+                let hour = (t / 60) % 24;
+                if self.hours & (1 << hour) == 0 {
+                    t = (t / 60 + 1) * 60;
+                    continue;
+                }
+                if self.minutes & (1 << (t % 60)) == 0 {
+                    t += 1;
+                    continue;
+                }
+                return Some(t * 60);
+            }
+            None
+        }
+    }
+
+    impl std::str::FromStr for CronExpr {
+        type Err = CronError;
+
+        fn from_str(s: &str) -> Result<Self, CronError> {
+            CronExpr::parse(s)
+        }
+    }
+}
 
 pub mod executor {
-    use std::{sync::{Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+    use std::{fmt, panic::{self, AssertUnwindSafe}, sync::{Arc, Condvar, Mutex, Weak, atomic::{AtomicBool, AtomicU64, Ordering}}, thread::{self, JoinHandle}, time::{Duration, Instant, UNIX_EPOCH}};
+    use crate::clock::{Clock, Listener, SystemClock};
+    use crate::cron::CronExpr;
 
-    #[derive(PartialEq)]
+    // gli stati sono ordinati: una chiusura può solo passare a uno stato successivo
+    #[derive(PartialEq, PartialOrd, Clone, Copy)]
     pub enum State {
         Open, 
+        Drain,
+        DrainNow,
         Close,
     }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum CloseMode {
+        // i task in coda partono alla loro scadenza, poi l'executor si ferma
+        Drain,
+        // i task in coda vengono eseguiti subito, senza attendere la scadenza
+        DrainNow,
+        // i task in coda vengono tolti e restituiti al chiamante
+        Abort,
+    }
+
+    pub type TaskFn = Arc<dyn Fn() + Send + Sync + 'static>;
+
+    // cosa fare dei tick di un task periodico che non sono stati eseguiti in tempo
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum MissedTicks {
+        // si salta al primo tick futuro
+        Skip,
+        // i tick persi vengono eseguiti uno dopo l'altro finché il task non è di nuovo in pari
+        CatchUp,
+    }
+
+    enum Schedule {
+        Once,
+        FixedRate(Duration, MissedTicks),
+        FixedDelay(Duration),
+        // il secondo campo è il tick (secondi unix) a cui corrisponde l'esecuzione in coda
+        Cron(CronExpr, u64, MissedTicks),
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Priority {
+        Low,
+        Normal,
+        High,
+    }
+
+    // ogni `DEFAULT_AGING` passato in coda dopo la scadenza un task sale di un livello di priorità
+    pub const DEFAULT_AGING: Duration = Duration::from_secs(1);
+
+    // numero d'ordine globale dei task, usato a parità di scadenza e priorità
+    static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+    struct Task {
+        f: TaskFn,
+        at: Instant,
+        priority: Priority,
+        seq: u64,
+        schedule: Schedule,
+        cancelled: Arc<AtomicBool>,
+    }
+
+    type Queue = Mutex<Vec<Task>>;
+
+    // restituito dai task periodici: cancel() toglie il task dalla coda e ne impedisce le esecuzioni successive
+    pub struct ScheduleHandle {
+        cancelled: Arc<AtomicBool>,
+        queue: Weak<Queue>,
+        condvar: Weak<Condvar>,
+    }
+
+    impl ScheduleHandle {
+        pub fn cancel(&self) {
+            self.cancelled.store(true, Ordering::SeqCst);
+            if let (Some(q), Some(cv)) = (self.queue.upgrade(), self.condvar.upgrade()) {
+                q.lock().unwrap().retain(|t| !Arc::ptr_eq(&t.cancelled, &self.cancelled));
+                cv.notify_all();
+            }
+        }
+
+        pub fn is_cancelled(&self) -> bool {
+            self.cancelled.load(Ordering::SeqCst)
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum JoinError {
+        // il task è andato in panic
+        Panicked,
+        // il task è stato cancellato, o scartato da close(true), prima di partire
+        Cancelled,
+        // join_timeout: il task non è terminato entro il tempo indicato
+        Timeout,
+        // il risultato è già stato restituito da un join_timeout precedente
+        AlreadyTaken,
+    }
+
+    impl fmt::Display for JoinError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                JoinError::Panicked => write!(f, "task panicked"),
+                JoinError::Cancelled => write!(f, "task was cancelled"),
+                JoinError::Timeout => write!(f, "timed out waiting for the task"),
+                JoinError::AlreadyTaken => write!(f, "task result already taken"),
+            }
+        }
+    }
+
+    impl std::error::Error for JoinError {}
+
+    enum Outcome<R> {
+        Pending,
+        Running,
+        Done(R),
+        Panicked,
+        Cancelled,
+        // il risultato è già stato restituito da join_timeout
+        Taken,
+    }
+
+    struct Completion<R> {
+        outcome: Mutex<Outcome<R>>,
+        condvar: Condvar,
+    }
+
+    impl<R> Completion<R> {
+        fn set(&self, outcome: Outcome<R>) {
+            *self.outcome.lock().unwrap_or_else(|e| e.into_inner()) = outcome;
+            self.condvar.notify_all();
+        }
+    }
+
+    // la closure FnOnce dell'utente, eseguibile una volta sola dal worker
+    struct Job<F, R> {
+        f: Mutex<Option<F>>,
+        completion: Arc<Completion<R>>,
+    }
+
+    impl<F: FnOnce() -> R, R> Job<F, R> {
+        fn run(&self) {
+            {
+                let mut o = self.completion.outcome.lock().unwrap();
+                if !matches!(*o, Outcome::Pending) {
+                    return;
+                }
+                *o = Outcome::Running;
+            }
+            let f = self.f.lock().unwrap().take().unwrap();
+            match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(r) => self.completion.set(Outcome::Done(r)),
+                Err(_) => self.completion.set(Outcome::Panicked),
+            }
+        }
+    }
+
+    // un job tolto dalla coda senza essere eseguito (cancel o close) sblocca chi fa join
+    impl<F, R> Drop for Job<F, R> {
+        fn drop(&mut self) {
+            let mut o = self.completion.outcome.lock().unwrap_or_else(|e| e.into_inner());
+            if matches!(*o, Outcome::Pending) {
+                *o = Outcome::Cancelled;
+                self.completion.condvar.notify_all();
+            }
+        }
+    }
+
+    // restituito da execute: permette di cancellare il task o di attenderne il risultato
+    pub struct TaskHandle<R> {
+        handle: ScheduleHandle,
+        completion: Arc<Completion<R>>,
+    }
+
+    impl<R> TaskHandle<R> {
+        // true se il task è stato cancellato prima di partire; un task già in esecuzione non viene interrotto
+        pub fn cancel(&self) -> bool {
+            let cancelled = {
+                let mut o = self.completion.outcome.lock().unwrap();
+                if matches!(*o, Outcome::Pending) {
+                    *o = Outcome::Cancelled;
+                    true
+                } else {
+                    false
+                }
+            };
+            if cancelled {
+                self.completion.condvar.notify_all();
+                self.handle.cancel();
+            }
+            cancelled
+        }
+
+        pub fn is_done(&self) -> bool {
+            !matches!(*self.completion.outcome.lock().unwrap(), Outcome::Pending | Outcome::Running)
+        }
+
+        pub fn join(self) -> Result<R, JoinError> {
+            let o = self.completion.outcome.lock().unwrap();
+            let o = self.completion.condvar.wait_while(o, |o| matches!(o, Outcome::Pending | Outcome::Running)).unwrap();
+            Self::take(o)
+        }
+
+        pub fn join_timeout(&self, timeout: Duration) -> Result<R, JoinError> {
+            let o = self.completion.outcome.lock().unwrap();
+            let (o, res) = self.completion.condvar.wait_timeout_while(o, timeout, |o| matches!(o, Outcome::Pending | Outcome::Running)).unwrap();
+            if res.timed_out() {
+                return Err(JoinError::Timeout);
+            }
+            Self::take(o)
+        }
+
+        fn take(mut o: std::sync::MutexGuard<'_, Outcome<R>>) -> Result<R, JoinError> {
+            match std::mem::replace(&mut *o, Outcome::Taken) {
+                Outcome::Done(r) => Ok(r),
+                Outcome::Panicked => {
+                    *o = Outcome::Panicked;
+                    Err(JoinError::Panicked)
+                },
+                Outcome::Cancelled => {
+                    *o = Outcome::Cancelled;
+                    Err(JoinError::Cancelled)
+                },
+                Outcome::Taken => Err(JoinError::AlreadyTaken),
+                Outcome::Pending | Outcome::Running => unreachable!(),
+            }
+        }
+    }
+
     pub struct DelayedExecutor {
-        queue: Arc<Mutex<Vec<(Box<dyn Fn() + Send + Sync + 'static>,Instant)>>>,
+        queue: Arc<Queue>,
         condvar: Arc<Condvar>,
         state: Arc<Mutex<State>>,
-        thread: Option<JoinHandle<()>>,
+        workers: Vec<JoinHandle<()>>,
+        alive: Alive,
+        aging: Arc<Mutex<Duration>>,
+        clock: Arc<dyn Clock>,
+        // registrato sul clock, che lo tiene solo finché esiste l'executor
+        _on_advance: Listener,
+    }
+
+    // numero di worker ancora attivi, usato da await_termination
+    type Alive = Arc<(Mutex<usize>, Condvar)>;
+
+    struct AliveGuard(Alive);
+
+    impl Drop for AliveGuard {
+        fn drop(&mut self) {
+            *self.0.0.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+            self.0.1.notify_all();
+        }
     }
 
     impl Drop for DelayedExecutor {
         fn drop(&mut self) {
-            self.close(true);
-            self.thread.take().unwrap().join().unwrap();
+            // senza una close esplicita i task in coda vengono scartati; un Drain già richiesto viene invece atteso
+            if *self.state.lock().unwrap() == State::Open {
+                self.close_with(CloseMode::Abort);
+            }
+            // i worker terminano dopo il task in corso.
+            // i panic dei task sono già isolati: un errore di join non va propagato dal drop
+            for w in self.workers.drain(..) {
+                let _ = w.join();
+            }
+        }
+    }
+
+    impl Default for DelayedExecutor {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    fn unix_secs(clock: &dyn Clock) -> u64 {
+        clock.system_time().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+
+    // istante del clock corrispondente al tick cron `tick` (secondi unix)
+    fn cron_instant(clock: &dyn Clock, tick: u64) -> Instant {
+        let wall = clock.system_time();
+        let target = UNIX_EPOCH + Duration::from_secs(tick);
+        clock.now() + target.duration_since(wall).unwrap_or(Duration::ZERO)
+    }
+
+    // prossima esecuzione di un task periodico appena eseguito; None se non va ripetuto
+    fn next_run(task: &mut Task, clock: &dyn Clock) -> Option<Instant> {
+        let now = clock.now();
+        match &mut task.schedule {
+            Schedule::Once => None,
+            Schedule::FixedRate(period, policy) => {
+                let mut next = task.at + *period;
+                if *policy == MissedTicks::Skip && next <= now {
+                    let behind = (now - next).as_nanos() / period.as_nanos().max(1) + 1;
+                    next += *period * behind as u32;
+                }
+                Some(next)
+            },
+            Schedule::FixedDelay(delay) => Some(now + *delay),
+            Schedule::Cron(expr, tick, policy) => {
+                let from = match policy {
+                    MissedTicks::CatchUp => *tick,
+                    MissedTicks::Skip => (*tick).max(unix_secs(clock)),
+                };
+                let next = expr.next_after(from)?;
+                *tick = next;
+                Some(cron_instant(clock, next))
+            },
+        }
+    }
+
+    fn push(q: &mut Vec<Task>, mut task: Task) {
+        task.seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+        q.push(task);
+        q.sort_by_key(|t| std::cmp::Reverse(t.at));
+    }
+
+    // indice del prossimo task da eseguire tra quelli scaduti (tutti se `all`): la scadenza decide
+    // quando un task può partire, tra quelli pronti vince la priorità più alta, che cresce di un
+    // livello ogni `aging` di ritardo; a parità la scadenza più vecchia e poi l'ordine di inserimento
+    fn next_due(q: &[Task], now: Instant, aging: Duration, all: bool) -> Option<usize> {
+        let aging = aging.as_nanos().max(1);
+        let rank = |t: &Task| t.priority as u128 + now.saturating_duration_since(t.at).as_nanos() / aging;
+        q.iter().enumerate().rev()
+            .take_while(|(_, t)| all || t.at <= now)
+            .max_by(|(_, a), (_, b)| rank(a).cmp(&rank(b)).then(b.at.cmp(&a.at)).then(b.seq.cmp(&a.seq)))
+            .map(|(i, _)| i)
+    }
+
+    // thread del pool: quando è libero prende, sotto il lock della coda, il task pronto con il rango
+    // più alto, così priorità e aging valgono anche per i task scaduti mentre tutti i worker erano occupati
+    fn worker(q: Arc<Queue>, cond: Arc<Condvar>, state: Arc<Mutex<State>>, aging: Arc<Mutex<Duration>>, clock: Arc<dyn Clock>) {
+        loop {
+            // lo stato si legge sempre dopo la coda, nello stesso ordine di execute e close
+            let mut q_c = q.lock().unwrap();
+            q_c = cond.wait_while(q_c, |q| q.is_empty() && *state.lock().unwrap() == State::Open).unwrap();
+
+            let st = *state.lock().unwrap();
+            if st == State::Close {
+                break;
+            }
+
+            let now = clock.now();
+            let due = next_due(&q_c, now, *aging.lock().unwrap(), st == State::DrainNow);
+            let mut task = match (due, q_c.last()) {
+                (Some(i), _) => q_c.remove(i),
+                (None, Some(task)) => {
+                    let at = task.at;
+                    drop(match clock.wait_for(at) {
+                        Some(d) => cond.wait_timeout(q_c, d).unwrap().0,
+                        None => cond.wait(q_c).unwrap(),
+                    });
+                    continue;
+                },
+                // coda vuota durante un Drain: non arriveranno altri task
+                (None, None) => break,
+            };
+            drop(q_c);
+
+            if task.cancelled.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            // un panic resta confinato al task; un task periodico che va in panic non viene più rieseguito
+            if panic::catch_unwind(AssertUnwindSafe(|| (task.f)())).is_err() {
+                task.cancelled.store(true, Ordering::SeqCst);
+                continue;
+            }
+
+            if let Some(at) = next_run(&mut task, &*clock) {
+                task.at = at;
+                let mut q_c = q.lock().unwrap();
+                if !task.cancelled.load(Ordering::SeqCst) && *state.lock().unwrap() == State::Open {
+                    push(&mut q_c, task);
+                    cond.notify_all();
+                }
+            }
         }
     }
 
     impl DelayedExecutor {
         pub fn new() -> Self {
-            let mut queue = Arc::new(Mutex::new(Vec::<(Box<dyn Fn() + Send + Sync + 'static>, Instant)>::new()));
+            Self::with_clock(Arc::new(SystemClock))
+        }
+
+        pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+            Self::with_clock_and_workers(clock, 1)
+        }
+
+        // i task scaduti vengono eseguiti in parallelo su `workers` thread
+        pub fn with_workers(workers: usize) -> Self {
+            Self::with_clock_and_workers(Arc::new(SystemClock), workers)
+        }
+
+        pub fn with_clock_and_workers(clock: Arc<dyn Clock>, workers: usize) -> Self {
+            assert!(workers > 0, "workers must be greater than zero");
+            let queue = Arc::new(Mutex::new(Vec::<Task>::new()));
             let cond = Arc::new(Condvar::new());
             let state = Arc::new(Mutex::new(State::Open));
+            let aging = Arc::new(Mutex::new(DEFAULT_AGING));
 
-            let mut q = Arc::clone(&queue);
-            let cond_c = Arc::clone(&cond);
-            let state_c = Arc::clone(&state);
-
-            let jh = thread::spawn(move || {
-                loop {
-                    let mut q_c = q.lock().unwrap();
-                    let mut s = state_c.lock().unwrap();
-
-                    if *s == State::Open {
-                        q_c = cond_c.wait_while(q_c, |q| q.len() == 0).unwrap();
-                    }
-                    else {
-                        break;
-                    }
-
-                    match q_c.last() {
-                        Some(tup) => {
-                            if tup.1 <= Instant::now() {
-                                (tup.0)();
-                                q_c.pop();
-                            }
-                            else {
-                                let d = tup.1 - Instant::now();
-                                q_c = cond_c.wait_timeout(q_c, d).unwrap().0;
-                            }
-                        },
-                        None => {
-                            break;
-                        }
-                    }
-                }
+            // con un clock finto il tempo avanza senza notifiche: i worker vanno svegliati a mano
+            let (q_c, cond_c) = (Arc::clone(&queue), Arc::clone(&cond));
+            let on_advance: Listener = Arc::new(move || {
+                let _q = q_c.lock().unwrap();
+                cond_c.notify_all();
             });
+            clock.on_advance(&on_advance);
+
+            let alive: Alive = Arc::new((Mutex::new(workers), Condvar::new()));
+            let workers = (0..workers).map(|_| {
+                let (q, cond, state, aging, clock) = (Arc::clone(&queue), Arc::clone(&cond), Arc::clone(&state), Arc::clone(&aging), Arc::clone(&clock));
+                let guard = AliveGuard(Arc::clone(&alive));
+                thread::spawn(move || {
+                    let _guard = guard;
+                    worker(q, cond, state, aging, clock)
+                })
+            }).collect();
 
             Self {
-                queue: queue,
+                queue,
                 condvar: cond,
-                state: state,
-                thread: Some(jh),
+                state,
+                workers,
+                alive,
+                aging,
+                clock,
+                _on_advance: on_advance,
             }
             
         }
 
-        pub fn execute<F>(&self, f: F, delay: Duration) -> bool where F: Fn() + Send + Sync + 'static {
+        // ritardo dopo la scadenza per cui un task guadagna un livello di priorità
+        pub fn with_aging(self, step: Duration) -> Self {
+            *self.aging.lock().unwrap() = step;
+            self
+        }
+
+        fn schedule(&self, f: TaskFn, at: Instant, priority: Priority, schedule: Schedule) -> Option<ScheduleHandle> {
             let mut q = self.queue.lock().unwrap();
             let state = self.state.lock().unwrap();
 
             if *state == State::Open {
                 drop(state);
-                let inst = Instant::now() + delay;
-                q.push((Box::new(f), inst));
-                q.sort_by(|a, b| b.1.cmp(&a.1));
+                let cancelled = Arc::new(AtomicBool::new(false));
+                push(&mut q, Task { f, at, priority, seq: 0, schedule, cancelled: Arc::clone(&cancelled) });
                 drop(q);
                 self.condvar.notify_all();
-                return true;
+                Some(ScheduleHandle { cancelled, queue: Arc::downgrade(&self.queue), condvar: Arc::downgrade(&self.condvar) })
             }
             else {
                 drop(state);
-                return false;
+                None
             }
         }
 
+        // esegue f una volta dopo `delay`; None se l'executor è chiuso
+        pub fn execute<F, R>(&self, f: F, delay: Duration) -> Option<TaskHandle<R>>
+        where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+            self.execute_with_priority(f, delay, Priority::Normal)
+        }
+
+        // come execute; se più task sono pronti insieme partono prima quelli con priorità più alta
+        pub fn execute_with_priority<F, R>(&self, f: F, delay: Duration, priority: Priority) -> Option<TaskHandle<R>>
+        where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+            let completion = Arc::new(Completion { outcome: Mutex::new(Outcome::Pending), condvar: Condvar::new() });
+            let job = Job { f: Mutex::new(Some(f)), completion: Arc::clone(&completion) };
+            let handle = self.schedule(Arc::new(move || job.run()), self.clock.now() + delay, priority, Schedule::Once)?;
+            Some(TaskHandle { handle, completion })
+        }
+
+        // esegue f dopo `initial_delay` e poi ogni `period`, misurato dall'inizio previsto dell'esecuzione precedente
+        pub fn schedule_at_fixed_rate<F>(&self, f: F, initial_delay: Duration, period: Duration, missed: MissedTicks) -> Option<ScheduleHandle>
+        where F: Fn() + Send + Sync + 'static {
+            assert!(!period.is_zero(), "period must be greater than zero");
+            self.schedule(Arc::new(f), self.clock.now() + initial_delay, Priority::Normal, Schedule::FixedRate(period, missed))
+        }
+
+        // esegue f dopo `initial_delay` e poi `delay` dopo la fine di ogni esecuzione
+        pub fn schedule_with_fixed_delay<F>(&self, f: F, initial_delay: Duration, delay: Duration) -> Option<ScheduleHandle>
+        where F: Fn() + Send + Sync + 'static {
+            self.schedule(Arc::new(f), self.clock.now() + initial_delay, Priority::Normal, Schedule::FixedDelay(delay))
+        }
+
+        // esegue f a ogni minuto che soddisfa `expr`
+        pub fn schedule_cron<F>(&self, f: F, expr: CronExpr, missed: MissedTicks) -> Option<ScheduleHandle>
+        where F: Fn() + Send + Sync + 'static {
+            let tick = expr.next_after(unix_secs(&*self.clock))?;
+            let at = cron_instant(&*self.clock, tick);
+            self.schedule(Arc::new(f), at, Priority::Normal, Schedule::Cron(expr, tick, missed))
+        }
+
+        // close(true) equivale a CloseMode::Abort scartando i task, close(false) a CloseMode::Drain
         pub fn close(&self, drop_pending_tasks: bool) {
+            self.close_with(if drop_pending_tasks { CloseMode::Abort } else { CloseMode::Drain });
+        }
+
+        // chiude l'executor: da qui in poi execute e schedule_* vengono rifiutati e i task periodici
+        // non vengono più rimessi in coda. Con Abort restituisce i task tolti dalla coda in ordine di
+        // scadenza: eseguirli completa i relativi TaskHandle, scartarli li cancella
+        pub fn close_with(&self, mode: CloseMode) -> Vec<TaskFn> {
             let mut q = self.queue.lock().unwrap();
             let mut state = self.state.lock().unwrap();
-            if drop_pending_tasks == true {
-                *state = State::Close;
-                self.condvar.notify_all();
-                q.clear();
+            let next = match mode {
+                CloseMode::Drain => State::Drain,
+                CloseMode::DrainNow => State::DrainNow,
+                CloseMode::Abort => State::Close,
+            };
+            if next > *state {
+                *state = next;
             }
+            self.condvar.notify_all();
+
+            if mode == CloseMode::Abort {
+                q.drain(..).rev().filter(|t| !t.cancelled.load(Ordering::SeqCst)).map(|t| t.f).collect()
+            } else {
+                Vec::new()
+            }
+        }
+
+        // attende al massimo `timeout` che l'executor, dopo una close, abbia terminato tutti i task;
+        // restituisce true se tutti i thread sono terminati
+        pub fn await_termination(&self, timeout: Duration) -> bool {
+            let (alive, cv) = &*self.alive;
+            let n = alive.lock().unwrap();
+            *cv.wait_timeout_while(n, timeout, |n| *n > 0).unwrap().0 == 0
         }
     }
 }
@@ -118,42 +759,33 @@ fn main() {
 
     // Task 1: dopo 1 secondo
     exec.execute(
-        {
-            let start = start.clone();
-            move || {
-                println!(
-                    "[{:>4} ms] Task 1 eseguito!",
-                    Instant::now().duration_since(start).as_millis()
-                );
-            }
+        move || {
+            println!(
+                "[{:>4} ms] Task 1 eseguito!",
+                Instant::now().duration_since(start).as_millis()
+            );
         },
         Duration::from_secs(1),
     );
 
     // Task 2: dopo 1.5 secondi
     exec.execute(
-        {
-            let start = start.clone();
-            move || {
-                println!(
-                    "[{:>4} ms] Task 2 eseguito!",
-                    Instant::now().duration_since(start).as_millis()
-                );
-            }
+        move || {
+            println!(
+                "[{:>4} ms] Task 2 eseguito!",
+                Instant::now().duration_since(start).as_millis()
+            );
         },
         Duration::from_millis(1500),
     );
 
     // Task 3: dopo 2 secondi
     exec.execute(
-        {
-            let start = start.clone();
-            move || {
-                println!(
-                    "[{:>4} ms] Task 3 eseguito!",
-                    Instant::now().duration_since(start).as_millis()
-                );
-            }
+        move || {
+            println!(
+                "[{:>4} ms] Task 3 eseguito!",
+                Instant::now().duration_since(start).as_millis()
+            );
         },
         Duration::from_secs(2),
     );
@@ -163,3 +795,369 @@ fn main() {
 
     // Quando `exec` esce dallo scope, drop() chiude e unisce il thread
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::{Clock, MockClock};
+    use cron::{CronError, CronExpr};
+    use executor::{CloseMode, JoinError, MissedTicks, Priority};
+    use std::sync::{Arc, mpsc};
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_tasks_fire_in_deadline_order_with_mock_clock() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+
+        for (id, secs) in [(3, 30), (1, 10), (2, 20)] {
+            let tx = tx.clone();
+            exec.execute(move || tx.send(id).unwrap(), Duration::from_secs(secs));
+        }
+
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_secs(15));
+        assert_eq!(rx.recv().unwrap(), 1);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_secs(15));
+        assert_eq!(rx.recv().unwrap(), 2);
+        assert_eq!(rx.recv().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_task_added_later_with_shorter_delay_runs_first() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+
+        let t = tx.clone();
+        exec.execute(move || t.send("late").unwrap(), Duration::from_secs(60));
+        clock.advance(Duration::from_secs(30));
+        exec.execute(move || tx.send("early").unwrap(), Duration::from_secs(10));
+
+        clock.advance(Duration::from_secs(40));
+        assert_eq!(rx.recv().unwrap(), "early");
+        assert_eq!(rx.recv().unwrap(), "late");
+        assert!(clock.now() > Instant::now());
+    }
+
+    #[test]
+    fn test_execute_after_close_is_rejected() {
+        let exec = DelayedExecutor::with_clock(Arc::new(clock::SystemClock));
+        exec.close(true);
+        assert!(exec.execute(|| {}, Duration::ZERO).is_none());
+        assert!(exec.schedule_with_fixed_delay(|| {}, Duration::ZERO, Duration::from_secs(1)).is_none());
+    }
+
+    // fa avanzare il clock un secondo alla volta, lasciando al worker il tempo di eseguire i task scaduti
+    fn step(clock: &MockClock, secs: u64) {
+        for _ in 0..secs {
+            clock.advance(Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_fixed_rate_and_cancel() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+        let c = clock.clone();
+        let start = clock.now();
+
+        let h = exec.schedule_at_fixed_rate(move || tx.send((c.now() - start).as_secs()).unwrap(),
+            Duration::from_secs(5), Duration::from_secs(10), MissedTicks::Skip).unwrap();
+
+        step(&clock, 26);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![5, 15, 25]);
+
+        h.cancel();
+        assert!(h.is_cancelled());
+        step(&clock, 20);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_fixed_rate_missed_ticks_policy() {
+        for (policy, expected) in [(MissedTicks::Skip, 1), (MissedTicks::CatchUp, 4)] {
+            let clock = MockClock::new();
+            let exec = DelayedExecutor::with_clock(clock.clone());
+            let (tx, rx) = mpsc::channel();
+
+            exec.schedule_at_fixed_rate(move || tx.send(()).unwrap(), Duration::from_secs(10), Duration::from_secs(10), policy);
+            // salto di 40 secondi in un colpo solo: i tick a 10, 20, 30 e 40 sono tutti scaduti
+            clock.advance(Duration::from_secs(40));
+            thread::sleep(Duration::from_millis(100));
+            assert_eq!(rx.try_iter().count(), expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_fixed_delay_counts_from_end_of_run() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+        let c = clock.clone();
+        let start = clock.now();
+
+        exec.schedule_with_fixed_delay(move || {
+            tx.send((c.now() - start).as_secs()).unwrap();
+            // l'esecuzione "dura" 3 secondi di tempo finto
+            c.advance(Duration::from_secs(3));
+        }, Duration::from_secs(2), Duration::from_secs(5)).unwrap();
+
+        step(&clock, 20);
+        assert_eq!(rx.try_iter().take(3).collect::<Vec<_>>(), vec![2, 10, 18]);
+    }
+
+    #[test]
+    fn test_cron_schedule() {
+        // 2024-01-31 23:50:00 UTC
+        let clock = MockClock::at(UNIX_EPOCH + Duration::from_secs(1_706_745_000));
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+        let c = clock.clone();
+
+        let expr = CronExpr::parse("0 0,12 1").unwrap();
+        exec.schedule_cron(move || tx.send(c.system_time()).unwrap(), expr, MissedTicks::Skip).unwrap();
+
+        clock.advance(Duration::from_secs(600));
+        let fired = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        // 2024-02-01 00:00:00 UTC
+        assert_eq!(fired, UNIX_EPOCH + Duration::from_secs(1_706_745_600));
+
+        clock.advance(Duration::from_secs(11 * 3600));
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), UNIX_EPOCH + Duration::from_secs(1_706_788_800));
+    }
+
+    #[test]
+    fn test_cron_parse_and_next() {
+        assert_eq!(CronExpr::parse("* *"), Err(CronError::FieldCount(2)));
+        assert_eq!(CronExpr::parse("60 * *"), Err(CronError::OutOfRange { field: "minute", value: 60 }));
+        assert_eq!(CronExpr::parse("* * 0"), Err(CronError::OutOfRange { field: "day", value: 0 }));
+        assert!(CronExpr::parse("*/0 * *").is_err());
+        assert!(CronExpr::parse("a * *").is_err());
+
+        let every_15 = CronExpr::parse("*/15 * *").unwrap();
+        assert_eq!(every_15.next_after(0), Some(15 * 60));
+        assert_eq!(every_15.next_after(15 * 60), Some(30 * 60));
+
+        // 1970-01-31 10:30 dopo il 1970-01-01
+        let monthly = CronExpr::parse("30 10-12/2 31").unwrap();
+        assert_eq!(monthly.next_after(0), Some(30 * 86400 + 10 * 3600 + 30 * 60));
+        // dal 31 gennaio 12:30 si salta al 31 marzo (febbraio non ha il 31)
+        assert_eq!(monthly.next_after(30 * 86400 + 12 * 3600 + 30 * 60), Some(89 * 86400 + 10 * 3600 + 30 * 60));
+    }
+
+    #[test]
+    fn test_join_returns_result() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+
+        let h = exec.execute(|| 6 * 7, Duration::from_secs(5)).unwrap();
+        assert!(!h.is_done());
+        assert_eq!(h.join_timeout(Duration::from_millis(50)), Err(JoinError::Timeout));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(h.join_timeout(Duration::from_secs(1)), Ok(42));
+        assert!(h.is_done());
+        assert_eq!(h.join_timeout(Duration::from_secs(1)), Err(JoinError::AlreadyTaken));
+        assert_eq!(h.join(), Err(JoinError::AlreadyTaken));
+    }
+
+    #[test]
+    fn test_cancelled_task_is_removed_and_never_runs() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+
+        let t = tx.clone();
+        let cancelled = exec.execute(move || t.send(1).unwrap(), Duration::from_secs(10)).unwrap();
+        let kept = exec.execute(move || tx.send(2).unwrap(), Duration::from_secs(20)).unwrap();
+
+        assert!(cancelled.cancel());
+        assert!(cancelled.is_done());
+        assert_eq!(cancelled.join(), Err(JoinError::Cancelled));
+
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(kept.join(), Ok(()));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![2]);
+        // un task già eseguito non si può più cancellare
+        let done = exec.execute(|| (), Duration::ZERO).unwrap();
+        while !done.is_done() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!done.cancel());
+    }
+
+    #[test]
+    fn test_panicking_task_reports_error_and_executor_survives() {
+        let exec = DelayedExecutor::with_clock(Arc::new(clock::SystemClock));
+        let h = exec.execute(|| -> u32 { panic!("boom") }, Duration::ZERO).unwrap();
+        assert_eq!(h.join(), Err(JoinError::Panicked));
+        assert_eq!(exec.execute(|| 1, Duration::ZERO).unwrap().join(), Ok(1));
+    }
+
+    #[test]
+    fn test_close_cancels_pending_handles() {
+        let exec = DelayedExecutor::with_clock(MockClock::new());
+        let h = exec.execute(|| 1, Duration::from_secs(60)).unwrap();
+        exec.close(true);
+        assert_eq!(h.join(), Err(JoinError::Cancelled));
+    }
+
+    #[test]
+    fn test_slow_task_does_not_delay_others_with_pool() {
+        let exec = DelayedExecutor::with_workers(2);
+        let (tx, rx) = mpsc::channel();
+
+        // con un solo worker il primo task resterebbe bloccato fino al timeout
+        let slow = exec.execute(move || rx.recv_timeout(Duration::from_secs(2)).is_ok(), Duration::ZERO).unwrap();
+        let fast = exec.execute(move || tx.send(()).unwrap(), Duration::from_millis(20)).unwrap();
+
+        assert_eq!(fast.join(), Ok(()));
+        assert_eq!(slow.join(), Ok(true));
+    }
+
+    #[test]
+    fn test_panicking_periodic_task_is_stopped() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock_and_workers(clock.clone(), 2);
+        let (tx, rx) = mpsc::channel();
+
+        let h = exec.schedule_at_fixed_rate(|| panic!("tick failed"), Duration::from_secs(1), Duration::from_secs(1), MissedTicks::Skip).unwrap();
+        exec.schedule_at_fixed_rate(move || tx.send(()).unwrap(), Duration::from_secs(1), Duration::from_secs(1), MissedTicks::Skip).unwrap();
+
+        step(&clock, 3);
+        // la stampa del panic può richiedere del tempo: si attende che il worker abbia finito
+        let start = Instant::now();
+        while !h.is_cancelled() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(h.is_cancelled());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(()));
+    }
+
+    #[test]
+    fn test_close_drain_runs_pending_at_their_deadline() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+        for (id, secs) in [(2, 20), (1, 10)] {
+            let tx = tx.clone();
+            exec.execute(move || tx.send(id).unwrap(), Duration::from_secs(secs));
+        }
+        let t = tx.clone();
+        exec.schedule_at_fixed_rate(move || t.send(0).unwrap(), Duration::from_secs(5), Duration::from_secs(5), MissedTicks::Skip);
+
+        assert!(exec.close_with(CloseMode::Drain).is_empty());
+        assert!(exec.execute(|| {}, Duration::ZERO).is_none());
+        assert!(!exec.await_termination(Duration::from_millis(50)));
+        assert!(rx.try_recv().is_err());
+
+        step(&clock, 20);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        // il task periodico gira una sola volta: dopo la close non viene più rimesso in coda
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_close_drain_now_runs_pending_immediately() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock_and_workers(clock.clone(), 2);
+        let handles: Vec<_> = (1..=3).map(|i| exec.execute(move || i * 10, Duration::from_secs(60 * i)).unwrap()).collect();
+
+        exec.close_with(CloseMode::DrainNow);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        assert_eq!(handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>(), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn test_close_abort_returns_pending_tasks() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+        for (id, secs) in [(2, 20), (1, 10), (3, 30)] {
+            let tx = tx.clone();
+            exec.execute(move || tx.send(id).unwrap(), Duration::from_secs(secs));
+        }
+        let cancelled = exec.execute(|| (), Duration::from_secs(5)).unwrap();
+        assert!(cancelled.cancel());
+        let dropped = exec.execute(|| 1, Duration::from_secs(5)).unwrap();
+
+        let pending = exec.close_with(CloseMode::Abort);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        assert_eq!(pending.len(), 4);
+        assert!(rx.try_recv().is_err());
+
+        // i task restituiti sono in ordine di scadenza e possono ancora essere eseguiti dal chiamante
+        for f in &pending[1..] {
+            f();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+        drop(pending);
+        assert_eq!(dropped.join(), Err(JoinError::Cancelled));
+    }
+
+    #[test]
+    fn test_ready_tasks_run_by_priority() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone());
+        let (tx, rx) = mpsc::channel();
+
+        for (name, priority) in [("low", Priority::Low), ("normal 1", Priority::Normal), ("high", Priority::High), ("normal 2", Priority::Normal)] {
+            let tx = tx.clone();
+            exec.execute_with_priority(move || tx.send(name).unwrap(), Duration::from_secs(10), priority);
+        }
+        // un task non ancora scaduto non passa avanti anche se ha priorità più alta
+        let t = tx.clone();
+        exec.execute_with_priority(move || t.send("later").unwrap(), Duration::from_secs(20), Priority::High);
+
+        clock.advance(Duration::from_secs(10));
+        let got: Vec<_> = (0..4).map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap()).collect();
+        assert_eq!(got, vec!["high", "normal 1", "normal 2", "low"]);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn test_aging_lets_late_low_priority_tasks_run_first() {
+        for (aging, expected) in [(Duration::from_secs(3600), ["high", "low"]), (Duration::from_secs(1), ["low", "high"])] {
+            let clock = MockClock::new();
+            let exec = DelayedExecutor::with_clock(clock.clone()).with_aging(aging);
+            let (tx, rx) = mpsc::channel();
+
+            let t = tx.clone();
+            exec.execute_with_priority(move || t.send("low").unwrap(), Duration::from_secs(1), Priority::Low);
+            exec.execute_with_priority(move || tx.send("high").unwrap(), Duration::from_secs(4), Priority::High);
+
+            // entrambi scadono insieme: "low" è in ritardo di 4 secondi, "high" di 1
+            clock.advance(Duration::from_secs(5));
+            let got: Vec<_> = (0..2).map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap()).collect();
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn test_priority_applies_to_tasks_due_while_worker_busy() {
+        let clock = MockClock::new();
+        let exec = DelayedExecutor::with_clock(clock.clone()).with_aging(Duration::from_secs(3600));
+        let (tx, rx) = mpsc::channel();
+        let (started_tx, started) = mpsc::channel();
+        let (release, gate) = mpsc::channel::<()>();
+
+        // l'unico worker resta occupato mentre scadono prima "low" e poi "high"
+        exec.execute(move || { started_tx.send(()).unwrap(); gate.recv().unwrap(); }, Duration::ZERO);
+        started.recv_timeout(Duration::from_secs(1)).unwrap();
+        let t = tx.clone();
+        exec.execute_with_priority(move || t.send("low").unwrap(), Duration::from_secs(1), Priority::Low);
+        exec.execute_with_priority(move || tx.send("high").unwrap(), Duration::from_secs(3), Priority::High);
+        clock.advance(Duration::from_secs(2));
+        clock.advance(Duration::from_secs(2));
+
+        release.send(()).unwrap();
+        let got: Vec<_> = (0..2).map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap()).collect();
+        assert_eq!(got, vec!["high", "low"]);
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use crate::queue::DelayedQueue;
+
+// implementazione precedente: Vec riordinato a ogni offer, estrazione dalla coda del Vec
+struct SortedQueue<T> {
+    items: Vec<(T, Instant)>,
+}
+
+impl<T> SortedQueue<T> {
+    fn offer(&mut self, t: T, i: Instant) {
+        self.items.push((t, i));
+        self.items.sort_by_key(|v| std::cmp::Reverse(v.1));
+    }
+
+    fn take(&mut self) -> Option<T> {
+        self.items.pop().map(|(t, _)| t)
+    }
+}
+
+// istanti già scaduti ma in ordine sparso, così ogni estrazione è immediata
+fn instants(n: usize) -> Vec<Instant> {
+    let base = Instant::now() - Duration::from_secs(3600);
+    (0..n as u64).map(|i| base + Duration::from_micros((i * 7919) % 1_000_000)).collect()
+}
+
+pub fn sorted(n: usize) -> (Duration, Duration) {
+    let at = instants(n);
+    let mut q = SortedQueue { items: Vec::new() };
+
+    let start = Instant::now();
+    for (i, t) in at.iter().enumerate() {
+        q.offer(i, *t);
+    }
+    let offer = start.elapsed();
+
+    let start = Instant::now();
+    while std::hint::black_box(q.take()).is_some() {}
+    (offer, start.elapsed())
+}
+
+pub fn heap(n: usize) -> (Duration, Duration) {
+    let at = instants(n);
+    let q = DelayedQueue::<usize>::new();
+
+    let start = Instant::now();
+    for (i, t) in at.iter().enumerate() {
+        q.offer(i, *t);
+    }
+    let offer = start.elapsed();
+
+    let start = Instant::now();
+    while std::hint::black_box(q.try_take()).is_some() {}
+    (offer, start.elapsed())
+}
+
+pub fn run() {
+    for n in [1_000, 10_000, 20_000] {
+        let (so, st) = sorted(n);
+        let (ho, ht) = heap(n);
+        println!("n={}", n);
+        println!("  sort-on-insert: offer {:?}, take {:?}", so, st);
+        println!("  binary heap   : offer {:?}, take {:?}", ho, ht);
+    }
+}
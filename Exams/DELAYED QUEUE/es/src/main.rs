@@ -1,71 +1,360 @@
+pub mod clock {
+    use std::sync::{Arc, Mutex, Weak};
+    use std::time::{Duration, Instant};
+
+    pub type Listener = Arc<dyn Fn() + Send + Sync>;
+
+    // sorgente del tempo per code e timer: nei test si usa MockClock e si fa avanzare il tempo a mano
+    pub trait Clock: Send + Sync {
+        fn now(&self) -> Instant;
+
+        // quanto attendere davvero sulla condvar per arrivare a `deadline`;
+        // None = attendere finché non arriva una notifica
+        fn wait_for(&self, deadline: Instant) -> Option<Duration>;
+
+        // `f` viene chiamata ogni volta che il tempo avanza senza che passi tempo reale;
+        // il clock la tiene con un riferimento debole, quindi resta attiva finché il chiamante tiene `f`
+        fn on_advance(&self, _f: &Listener) {}
+    }
+
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn wait_for(&self, deadline: Instant) -> Option<Duration> {
+            Some(deadline.saturating_duration_since(Instant::now()))
+        }
+    }
+
+    pub struct MockClock {
+        now: Mutex<Instant>,
+        listeners: Mutex<Vec<Weak<dyn Fn() + Send + Sync>>>,
+    }
+
+    impl MockClock {
+        pub fn new() -> Arc<Self> {
+            Arc::new(MockClock { now: Mutex::new(Instant::now()), listeners: Mutex::new(Vec::new()) })
+        }
+
+        pub fn advance(&self, d: Duration) {
+            *self.now.lock().unwrap() += d;
+            // i listener di code ed executor già distrutti vengono tolti
+            let listeners: Vec<Listener> = {
+                let mut l = self.listeners.lock().unwrap();
+                l.retain(|f| f.strong_count() > 0);
+                l.iter().filter_map(Weak::upgrade).collect()
+            };
+            for f in listeners {
+                f();
+            }
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn wait_for(&self, _deadline: Instant) -> Option<Duration> {
+            None
+        }
+
+        fn on_advance(&self, f: &Listener) {
+            self.listeners.lock().unwrap().push(Arc::downgrade(f));
+        }
+    }
+}
+
 pub mod queue {
 
+    use std::cmp::Reverse;
+    use std::collections::{BinaryHeap, HashMap};
     use std::sync::{Arc, Mutex, Condvar};
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
+    use crate::clock::{Clock, Listener, SystemClock};
 
-   
+    #[derive(Debug, PartialEq)]
+    pub enum TakeError {
+        Timeout,
+        Closed,
+    }
+
+    // identifica un elemento inserito, per rimuoverlo o cambiarne la scadenza
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Handle(u64);
+
+    struct Item<T> {
+        value: T,
+        seq: u64,
+    }
+
+    // min-heap su (scadenza, numero di sequenza): a parità di istante esce prima chi è entrato prima.
+    // remove e reschedule non toccano l'heap: il nodo vecchio resta e viene scartato quando arriva in cima
+    struct Inner<T> {
+        heap: BinaryHeap<Reverse<(Instant, u64, u64)>>,
+        items: HashMap<u64, Item<T>>,
+        next_seq: u64,
+        closed: bool,
+    }
+
+    impl<T> Inner<T> {
+        fn push(&mut self, value: T, at: Instant) -> Handle {
+            let id = self.next_seq;
+            self.next_seq += 1;
+            self.items.insert(id, Item { value, seq: id });
+            self.heap.push(Reverse((at, id, id)));
+            Handle(id)
+        }
+
+        // scadenza dell'elemento in testa, scartando i nodi non più validi
+        fn head(&mut self) -> Option<Instant> {
+            while let Some(Reverse((at, seq, id))) = self.heap.peek() {
+                if matches!(self.items.get(id), Some(item) if item.seq == *seq) {
+                    return Some(*at);
+                }
+                self.heap.pop();
+            }
+            None
+        }
+
+        fn pop(&mut self) -> Option<T> {
+            self.head()?;
+            let Reverse((_, _, id)) = self.heap.pop().unwrap();
+            self.items.remove(&id).map(|item| item.value)
+        }
+
+        fn remove(&mut self, h: Handle) -> Option<T> {
+            let item = self.items.remove(&h.0)?;
+            self.compact();
+            Some(item.value)
+        }
+
+        fn reschedule(&mut self, h: Handle, at: Instant) -> bool {
+            let seq = self.next_seq;
+            let Some(item) = self.items.get_mut(&h.0) else {
+                return false;
+            };
+            item.seq = seq;
+            self.next_seq += 1;
+            self.heap.push(Reverse((at, seq, h.0)));
+            self.compact();
+            true
+        }
+
+        // ricostruisce l'heap quando i nodi scartati sono più di quelli validi
+        fn compact(&mut self) {
+            if self.heap.len() > 2 * self.items.len() + 16 {
+                let items = &self.items;
+                self.heap.retain(|Reverse((_, seq, id))| matches!(items.get(id), Some(item) if item.seq == *seq));
+            }
+        }
+    }
 
     pub struct DelayedQueue<T: Send + Clone> {
-        queue: Arc<Mutex<Vec<(T, Instant)>>>,
+        queue: Arc<Mutex<Inner<T>>>,
         condvar: Arc<Condvar>,
-        size : usize,
+        clock: Arc<dyn Clock>,
+        // registrato sul clock, che lo tiene solo finché esiste la coda
+        _on_advance: Listener,
     }
 
-    impl<T: Send + Clone> DelayedQueue<T> {
+    impl<T: Send + Clone + 'static> Default for DelayedQueue<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T: Send + Clone + 'static> DelayedQueue<T> {
         pub fn new() -> Self {
-            DelayedQueue {
-                queue: Arc::new(Mutex::new(Vec::<(T, Instant)>::new())),
-                condvar: Arc::new(Condvar::new()),
-                size: 0,
+            Self::with_clock(Arc::new(SystemClock))
+        }
+
+        pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+            let queue = Arc::new(Mutex::new(Inner { heap: BinaryHeap::new(), items: HashMap::new(), next_seq: 0, closed: false }));
+            let condvar = Arc::new(Condvar::new());
+
+            // quando il tempo avanza chi attende deve ricontrollare la testa; il lock evita notifiche perse
+            let (q_c, cv_c) = (Arc::clone(&queue), Arc::clone(&condvar));
+            let on_advance: Listener = Arc::new(move || {
+                let _q = q_c.lock().unwrap();
+                cv_c.notify_all();
+            });
+            clock.on_advance(&on_advance);
+
+            DelayedQueue { queue, condvar, clock, _on_advance: on_advance }
+        }
+
+        // restituisce None se la coda è stata chiusa
+        pub fn offer(&self, t:T, i: Instant) -> Option<Handle> {
+            let mut q = self.queue.lock().unwrap();
+            if q.closed {
+                return None;
             }
+            let h = q.push(t, i);
+            self.condvar.notify_all();
+            Some(h)
+        }
+
+        // toglie l'elemento dalla coda, se non è già stato estratto
+        pub fn remove(&self, h: Handle) -> Option<T> {
+            let mut q = self.queue.lock().unwrap();
+            let t = q.remove(h);
+            self.condvar.notify_all();
+            t
         }
 
-        pub fn offer(&mut self, t:T, i: Instant) {
+        // cambia la scadenza di un elemento ancora in coda
+        pub fn reschedule(&self, h: Handle, i: Instant) -> bool {
             let mut q = self.queue.lock().unwrap();
-            q.push((t, i));
-            self.size += 1;
+            let done = q.reschedule(h, i);
             self.condvar.notify_all();
-            q.sort_by(|v1, v2| v2.1.cmp(&v1.1));
+            done
+        }
+
+        // come da specifica: None se la coda è vuota, altrimenti attende la scadenza del primo elemento
+        pub fn take(&self) -> Option<T> {
+            self.wait_head(None, false).ok()
         }
 
-        pub fn take(&mut self) -> Option<T> {
+        // attende anche che arrivi un elemento; None solo se la coda è chiusa e vuota
+        pub fn take_blocking(&self) -> Option<T> {
+            self.wait_head(None, true).ok()
+        }
+
+        // come take_blocking, ma rinuncia dopo `timeout`
+        pub fn take_timeout(&self, timeout: Duration) -> Result<T, TakeError> {
+            self.wait_head(Some(self.clock.now() + timeout), true)
+        }
+
+        // solo un elemento già scaduto, senza attendere
+        pub fn try_take(&self) -> Option<T> {
+            let mut q = self.queue.lock().unwrap();
+            match q.head() {
+                Some(i) if i <= self.clock.now() => q.pop(),
+                _ => None,
+            }
+        }
+
+        // le offer successive falliscono; gli elementi già in coda restano prelevabili alla loro scadenza
+        // e chi attende su una coda vuota riceve None/Closed
+        pub fn close(&self) {
+            let mut q = self.queue.lock().unwrap();
+            q.closed = true;
+            self.condvar.notify_all();
+        }
+
+        pub fn is_closed(&self) -> bool {
+            self.queue.lock().unwrap().closed
+        }
+
+        pub fn size(&self) -> usize {
+            self.len()
+        }
+
+        // elementi in coda, scaduti o no, letti sotto lock
+        pub fn len(&self) -> usize {
+            self.queue.lock().unwrap().items.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        // iteratore bloccante: restituisce gli elementi man mano che scadono e termina quando la coda è chiusa e vuota
+        pub fn iter(&self) -> Iter<'_, T> {
+            Iter { queue: self }
+        }
+
+        // solo gli elementi già scaduti, senza attendere gli altri
+        pub fn drain_ready(&self) -> DrainReady<'_, T> {
+            DrainReady { queue: self }
+        }
+
+        // attende che l'elemento in testa scada, ripartendo a ogni modifica della coda;
+        // con `block` a false una coda vuota restituisce subito Timeout
+        fn wait_head(&self, deadline: Option<Instant>, block: bool) -> Result<T, TakeError> {
             let mut q = self.queue.lock().unwrap();
 
             loop {
-                match q.last() {
-                    Some(val) => {
-                        let (t, i) = val;
-                        if *i <= Instant::now() {
-                            self.size -= 1;
-                            let val = q.pop().unwrap();
-                            return Some(val.0);
-                        }
-                        else {
-                            let delay = *i - Instant::now();
-                            let q_new = self.condvar.wait_timeout(q, delay).unwrap().0;
-                            q = q_new;
-                        }
-                    },
-                    None => {
-                        return None;
-                    },
+                let now = self.clock.now();
+                let wake_at = match q.head() {
+                    Some(i) if i <= now => return Ok(q.pop().unwrap()),
+                    Some(i) => Some(i),
+                    // dopo la close si svuota la coda, poi si segnala la chiusura
+                    None if q.closed => return Err(TakeError::Closed),
+                    None if !block => return Err(TakeError::Timeout),
+                    None => None,
+                };
+
+                // si attende fino alla scadenza più vicina tra quella del primo elemento e la deadline
+                let wake_at = match (wake_at, deadline) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                if let Some(d) = deadline && d <= now {
+                    return Err(TakeError::Timeout);
                 }
+
+                q = match wake_at.and_then(|t| self.clock.wait_for(t)) {
+                    Some(d) => self.condvar.wait_timeout(q, d).unwrap().0,
+                    None => self.condvar.wait(q).unwrap(),
+                };
             }
         }
+    }
 
-        pub fn size(&self) -> usize {
-            self.size
+    pub struct Iter<'a, T: Send + Clone + 'static> {
+        queue: &'a DelayedQueue<T>,
+    }
+
+    impl<T: Send + Clone + 'static> Iterator for Iter<'_, T> {
+        type Item = T;
+
+        fn next(&mut self) -> Option<T> {
+            self.queue.take_blocking()
+        }
+    }
+
+    impl<'a, T: Send + Clone + 'static> IntoIterator for &'a DelayedQueue<T> {
+        type Item = T;
+        type IntoIter = Iter<'a, T>;
+
+        fn into_iter(self) -> Iter<'a, T> {
+            self.iter()
+        }
+    }
+
+    pub struct DrainReady<'a, T: Send + Clone + 'static> {
+        queue: &'a DelayedQueue<T>,
+    }
+
+    impl<T: Send + Clone + 'static> Iterator for DrainReady<'_, T> {
+        type Item = T;
+
+        fn next(&mut self) -> Option<T> {
+            self.queue.try_take()
         }
     }
 }
 
+mod bench;
+
 use std::time::{Duration, Instant};
 use queue::DelayedQueue;
 
 fn main() {
+    // `cargo run --release -- bench` confronta l'heap con la vecchia coda riordinata a ogni offer
+    if std::env::args().nth(1).as_deref() == Some("bench") {
+        bench::run();
+        return;
+    }
+
     println!("Test DelayedQueue");
     
-    let mut delayed_queue = DelayedQueue::<String>::new();
+    let delayed_queue = DelayedQueue::<String>::new();
     
     // Aggiungi elementi con delay diversi
     let now = Instant::now();
@@ -87,6 +376,281 @@ fn main() {
     println!("Test completato!");
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::{Clock, MockClock};
+    use queue::TakeError;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_take_returns_items_by_instant() {
+        let q = DelayedQueue::<u32>::new();
+        let now = Instant::now();
+        q.offer(2, now + Duration::from_millis(40));
+        q.offer(1, now + Duration::from_millis(20));
+        q.offer(3, now + Duration::from_millis(60));
+
+        assert_eq!(q.size(), 3);
+        assert_eq!(q.take(), Some(1));
+        assert!(Instant::now() >= now + Duration::from_millis(20));
+        assert_eq!(q.take(), Some(2));
+        assert_eq!(q.take(), Some(3));
+        assert_eq!(q.take(), None);
+    }
+
+    #[test]
+    fn test_try_take_does_not_wait() {
+        let q = DelayedQueue::<u32>::new();
+        assert_eq!(q.try_take(), None);
+        q.offer(1, Instant::now() + Duration::from_secs(10));
+        assert_eq!(q.try_take(), None);
+        q.offer(2, Instant::now());
+        assert_eq!(q.try_take(), Some(2));
+        assert_eq!(q.size(), 1);
+    }
+
+    #[test]
+    fn test_take_blocking_waits_for_an_offer() {
+        let q = Arc::new(DelayedQueue::<u32>::new());
+        let qc = Arc::clone(&q);
+        let consumer = thread::spawn(move || qc.take_blocking());
+
+        thread::sleep(Duration::from_millis(50));
+        q.offer(7, Instant::now() + Duration::from_millis(20));
+        assert_eq!(consumer.join().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn test_take_timeout_gives_up_after_deadline() {
+        let q = DelayedQueue::<u32>::new();
+        let start = Instant::now();
+        assert_eq!(q.take_timeout(Duration::from_millis(50)), Err(TakeError::Timeout));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // l'elemento scade dopo la deadline: resta in coda
+        q.offer(1, Instant::now() + Duration::from_millis(200));
+        assert_eq!(q.take_timeout(Duration::from_millis(20)), Err(TakeError::Timeout));
+        assert_eq!(q.size(), 1);
+        assert_eq!(q.take_timeout(Duration::from_millis(500)), Ok(1));
+    }
+
+    #[test]
+    fn test_close_wakes_blocked_takers() {
+        let q = Arc::new(DelayedQueue::<u32>::new());
+        let takers: Vec<_> = (0..3).map(|i| {
+            let q = Arc::clone(&q);
+            thread::spawn(move || if i == 0 { q.take_blocking().ok_or(TakeError::Closed) } else { q.take_timeout(Duration::from_secs(10)) })
+        }).collect();
+
+        thread::sleep(Duration::from_millis(50));
+        q.close();
+        for t in takers {
+            assert_eq!(t.join().unwrap(), Err(TakeError::Closed));
+        }
+        assert!(q.offer(2, Instant::now()).is_none());
+        assert_eq!(q.take(), None);
+    }
+
+    #[test]
+    fn test_close_keeps_pending_items_takeable() {
+        let clock = MockClock::new();
+        let q = Arc::new(DelayedQueue::<u32>::with_clock(clock.clone()));
+        let t0 = clock.now();
+        q.offer(1, t0);
+        q.offer(2, t0 + Duration::from_secs(10));
+        q.close();
+
+        assert!(q.offer(3, t0).is_none());
+        assert_eq!(q.try_take(), Some(1));
+        assert_eq!(q.try_take(), None);
+
+        // l'elemento non ancora scaduto esce alla sua scadenza, poi l'iteratore termina
+        let qc = Arc::clone(&q);
+        let consumer = thread::spawn(move || qc.iter().collect::<Vec<_>>());
+        thread::sleep(Duration::from_millis(30));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(consumer.join().unwrap(), vec![2]);
+        assert_eq!(q.take_timeout(Duration::from_secs(1)), Err(TakeError::Closed));
+    }
+
+    #[test]
+    fn test_equal_instants_are_fifo() {
+        let q = DelayedQueue::<u32>::new();
+        let at = Instant::now();
+        for i in 0..5 {
+            q.offer(i, at);
+        }
+        let out: Vec<u32> = (0..5).map(|_| q.take().unwrap()).collect();
+        assert_eq!(out, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_remove_and_reschedule_by_handle() {
+        let q = DelayedQueue::<u32>::new();
+        let now = Instant::now();
+        let a = q.offer(1, now + Duration::from_millis(10)).unwrap();
+        let b = q.offer(2, now + Duration::from_millis(20)).unwrap();
+        q.offer(3, now + Duration::from_millis(30));
+
+        assert_eq!(q.remove(a), Some(1));
+        assert_eq!(q.remove(a), None);
+        assert!(q.reschedule(b, now + Duration::from_millis(40)));
+        assert_eq!(q.size(), 2);
+
+        assert_eq!(q.take(), Some(3));
+        assert_eq!(q.take(), Some(2));
+        assert!(!q.reschedule(b, now));
+        assert_eq!(q.take(), None);
+    }
+
+    #[test]
+    fn test_reschedule_wakes_waiting_taker() {
+        let q = Arc::new(DelayedQueue::<u32>::new());
+        let h = q.offer(1, Instant::now() + Duration::from_secs(10)).unwrap();
+        let qc = Arc::clone(&q);
+        let start = Instant::now();
+        let taker = thread::spawn(move || qc.take());
+
+        thread::sleep(Duration::from_millis(30));
+        q.reschedule(h, Instant::now());
+        assert_eq!(taker.join().unwrap(), Some(1));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_many_reschedules_keep_heap_bounded() {
+        let q = DelayedQueue::<u32>::new();
+        let now = Instant::now();
+        let h = q.offer(1, now).unwrap();
+        for i in 0..1000 {
+            q.reschedule(h, now + Duration::from_secs(10) + Duration::from_millis(i));
+        }
+        assert_eq!(q.size(), 1);
+        assert_eq!(q.try_take(), None);
+        q.reschedule(h, now);
+        assert_eq!(q.try_take(), Some(1));
+    }
+
+    #[test]
+    fn test_mock_clock_releases_items_in_order() {
+        let clock = MockClock::new();
+        let q = Arc::new(DelayedQueue::<u32>::with_clock(clock.clone()));
+        let t0 = clock.now();
+        q.offer(3, t0 + Duration::from_secs(30));
+        q.offer(1, t0 + Duration::from_secs(10));
+        q.offer(2, t0 + Duration::from_secs(20));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let qc = Arc::clone(&q);
+        let consumer = thread::spawn(move || {
+            while let Some(v) = qc.take() {
+                tx.send(v).unwrap();
+            }
+        });
+
+        // nessun elemento esce finché il tempo finto non avanza
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(rx.recv().unwrap(), 1);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_secs(25));
+        assert_eq!(rx.recv().unwrap(), 2);
+        assert_eq!(rx.recv().unwrap(), 3);
+        consumer.join().unwrap();
+    }
+
+    #[test]
+    fn test_mock_clock_does_not_keep_dropped_queues_alive() {
+        let clock = MockClock::new();
+        let item = Arc::new(());
+        let q = DelayedQueue::with_clock(clock.clone());
+        q.offer(Arc::clone(&item), clock.now() + Duration::from_secs(10));
+        drop(q);
+
+        // il clock sopravvive alla coda senza trattenerne gli elementi
+        assert_eq!(Arc::strong_count(&item), 1);
+        clock.advance(Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_mock_clock_take_timeout() {
+        let clock = MockClock::new();
+        let q = Arc::new(DelayedQueue::<u32>::with_clock(clock.clone()));
+        let qc = Arc::clone(&q);
+        let taker = thread::spawn(move || qc.take_timeout(Duration::from_secs(5)));
 
+        thread::sleep(Duration::from_millis(30));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(taker.join().unwrap(), Err(TakeError::Timeout));
+    }
 
+    #[test]
+    fn test_drain_ready_skips_future_items() {
+        let q = DelayedQueue::<u32>::new();
+        let now = Instant::now();
+        q.offer(1, now);
+        q.offer(2, now);
+        q.offer(3, now + Duration::from_secs(10));
+
+        assert_eq!(q.drain_ready().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(q.len(), 1);
+        assert!(!q.is_empty());
+    }
+
+    #[test]
+    fn test_iterator_yields_until_closed() {
+        let q = Arc::new(DelayedQueue::<u32>::new());
+        let now = Instant::now();
+        q.offer(2, now + Duration::from_millis(40));
+        q.offer(1, now + Duration::from_millis(20));
+
+        let qc = Arc::clone(&q);
+        let consumer = thread::spawn(move || qc.iter().collect::<Vec<_>>());
+        thread::sleep(Duration::from_millis(100));
+        q.close();
+        assert_eq!(consumer.join().unwrap(), vec![1, 2]);
+    }
 
+    #[test]
+    fn test_stress_many_producers_and_consumers() {
+        const PRODUCERS: u64 = 8;
+        const ITEMS: u64 = 500;
+
+        let q = Arc::new(DelayedQueue::<(u64, Instant)>::new());
+        let consumers: Vec<_> = (0..6).map(|_| {
+            let q = Arc::clone(&q);
+            thread::spawn(move || {
+                let mut got = vec![];
+                for (id, due) in &*q {
+                    assert!(Instant::now() >= due, "item {} taken before its instant", id);
+                    got.push(id);
+                }
+                got
+            })
+        }).collect();
+
+        let producers: Vec<_> = (0..PRODUCERS).map(|p| {
+            let q = Arc::clone(&q);
+            thread::spawn(move || {
+                for i in 0..ITEMS {
+                    let due = Instant::now() + Duration::from_micros((i * 37 + p * 11) % 5000);
+                    q.offer((p * ITEMS + i, due), due);
+                }
+            })
+        }).collect();
+
+        for p in producers {
+            p.join().unwrap();
+        }
+        while !q.is_empty() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        q.close();
+
+        let mut all: Vec<u64> = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect();
+        all.sort();
+        assert_eq!(all, (0..PRODUCERS * ITEMS).collect::<Vec<_>>());
+    }
+}
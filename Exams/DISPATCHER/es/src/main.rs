@@ -1,57 +1,678 @@
+pub mod topic {
+    use std::collections::HashMap;
+    use std::fmt;
+
+    // pattern di sottoscrizione: segmenti separati da '.', dove `*` corrisponde a esattamente
+    // un segmento e `#`, solo come ultimo segmento, a zero o più segmenti finali.
+    //   "sensors.kitchen.temp"  esatto
+    //   "sensors.*.temp"        wildcard
+    //   "sensors.#"             prefisso (anche "sensors" stesso)
+    #[derive(Debug, PartialEq)]
+    pub enum PatternError {
+        EmptySegment,
+        // `#` usato in una posizione diversa dall'ultima
+        MisplacedHash,
+    }
+
+    impl fmt::Display for PatternError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                PatternError::EmptySegment => write!(f, "topic pattern contains an empty segment"),
+                PatternError::MisplacedHash => write!(f, "'#' is only allowed as the last segment"),
+            }
+        }
+    }
+
+    impl std::error::Error for PatternError {}
+
+    pub fn validate(pattern: &str) -> Result<(), PatternError> {
+        let segments: Vec<&str> = pattern.split('.').collect();
+        for (i, s) in segments.iter().enumerate() {
+            if s.is_empty() {
+                return Err(PatternError::EmptySegment);
+            }
+            if *s == "#" && i + 1 != segments.len() {
+                return Err(PatternError::MisplacedHash);
+            }
+        }
+        Ok(())
+    }
+
+    struct Node {
+        children: HashMap<String, Node>,
+        star: Option<Box<Node>>,
+        // sottoscrizioni che terminano qui
+        exact: Vec<u64>,
+        // sottoscrizioni con `#` dopo questo nodo
+        rest: Vec<u64>,
+    }
+
+    impl Node {
+        fn new() -> Self {
+            Node { children: HashMap::new(), star: None, exact: Vec::new(), rest: Vec::new() }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.children.is_empty() && self.star.is_none() && self.exact.is_empty() && self.rest.is_empty()
+        }
+    }
+
+    // il costo di una ricerca dipende dalla profondità del topic e dai wildcard attraversati,
+    // non dal numero di sottoscrizioni
+    pub struct TopicTrie {
+        root: Node,
+    }
+
+    impl Default for TopicTrie {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl TopicTrie {
+        pub fn new() -> Self {
+            TopicTrie { root: Node::new() }
+        }
+
+        pub fn insert(&mut self, pattern: &str, id: u64) -> Result<(), PatternError> {
+            validate(pattern)?;
+            let mut node = &mut self.root;
+            for s in pattern.split('.') {
+                node = match s {
+                    "#" => {
+                        node.rest.push(id);
+                        return Ok(());
+                    },
+                    "*" => node.star.get_or_insert_with(|| Box::new(Node::new())),
+                    s => node.children.entry(s.to_string()).or_insert_with(Node::new),
+                };
+            }
+            node.exact.push(id);
+            Ok(())
+        }
+
+        // toglie `id` registrato con `pattern` e pota i nodi rimasti vuoti
+        pub fn remove(&mut self, pattern: &str, id: u64) -> bool {
+            fn remove_in(node: &mut Node, segments: &[&str], id: u64) -> bool {
+                let list = match segments {
+                    [] => &mut node.exact,
+                    ["#"] => &mut node.rest,
+                    [s, tail @ ..] => {
+                        let removed = match *s {
+                            "*" => node.star.as_deref_mut().is_some_and(|n| remove_in(n, tail, id)),
+                            s => node.children.get_mut(s).is_some_and(|n| remove_in(n, tail, id)),
+                        };
+                        if node.star.as_ref().is_some_and(|n| n.is_empty()) {
+                            node.star = None;
+                        }
+                        if node.children.get(*s).is_some_and(|n| n.is_empty()) {
+                            node.children.remove(*s);
+                        }
+                        return removed;
+                    },
+                };
+                match list.iter().position(|&x| x == id) {
+                    Some(i) => {
+                        list.swap_remove(i);
+                        true
+                    },
+                    None => false,
+                }
+            }
+            let segments: Vec<&str> = pattern.split('.').collect();
+            remove_in(&mut self.root, &segments, id)
+        }
+
+        // sottoscrizioni il cui pattern corrisponde a `topic`
+        pub fn matches(&self, topic: &str) -> Vec<u64> {
+            fn walk(node: &Node, segments: &[&str], out: &mut Vec<u64>) {
+                out.extend_from_slice(&node.rest);
+                match segments {
+                    [] => out.extend_from_slice(&node.exact),
+                    [s, tail @ ..] => {
+                        if let Some(child) = node.children.get(*s) {
+                            walk(child, tail, out);
+                        }
+                        if let Some(star) = &node.star {
+                            walk(star, tail, out);
+                        }
+                    },
+                }
+            }
+            let mut out = Vec::new();
+            let segments: Vec<&str> = topic.split('.').collect();
+            walk(&self.root, &segments, &mut out);
+            out
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_exact_wildcard_and_prefix() {
+            let mut trie = TopicTrie::new();
+            trie.insert("sensors.kitchen.temp", 1).unwrap();
+            trie.insert("sensors.*.temp", 2).unwrap();
+            trie.insert("sensors.#", 3).unwrap();
+            trie.insert("#", 4).unwrap();
+            trie.insert("alerts", 5).unwrap();
+
+            let sorted = |mut v: Vec<u64>| { v.sort(); v };
+            assert_eq!(sorted(trie.matches("sensors.kitchen.temp")), vec![1, 2, 3, 4]);
+            assert_eq!(sorted(trie.matches("sensors.garage.temp")), vec![2, 3, 4]);
+            assert_eq!(sorted(trie.matches("sensors.garage.humidity")), vec![3, 4]);
+            assert_eq!(sorted(trie.matches("sensors")), vec![3, 4]);
+            assert_eq!(sorted(trie.matches("alerts")), vec![4, 5]);
+            assert_eq!(sorted(trie.matches("alerts.fire")), vec![4]);
+        }
+
+        #[test]
+        fn test_remove_prunes_nodes() {
+            let mut trie = TopicTrie::new();
+            trie.insert("a.*.c", 1).unwrap();
+            trie.insert("a.b.#", 2).unwrap();
+            assert!(trie.remove("a.*.c", 1));
+            assert!(!trie.remove("a.*.c", 1));
+            assert_eq!(trie.matches("a.b.c"), vec![2]);
+            assert!(trie.remove("a.b.#", 2));
+            assert!(trie.root.is_empty());
+        }
+
+        #[test]
+        fn test_invalid_patterns() {
+            assert_eq!(validate("a..b"), Err(PatternError::EmptySegment));
+            assert_eq!(validate(""), Err(PatternError::EmptySegment));
+            assert_eq!(validate("a.#.b"), Err(PatternError::MisplacedHash));
+            assert!(TopicTrie::new().insert("a.#.b", 1).is_err());
+        }
+    }
+}
+
+
+pub mod bounded {
+    use std::collections::VecDeque;
+    use std::sync::{Condvar, Mutex};
+
+    // cosa fare quando la coda di un sottoscrittore è piena
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Overflow {
+        // chi pubblica attende che il sottoscrittore legga
+        Block,
+        // il nuovo messaggio viene scartato
+        DropNewest,
+        // viene scartato il messaggio più vecchio in coda
+        DropOldest,
+        // il sottoscrittore lento viene scollegato: legge quanto ha in coda e poi riceve None
+        Disconnect,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Metrics {
+        pub id: u64,
+        // messaggi in coda non ancora letti
+        pub lag: usize,
+        pub delivered: u64,
+        pub dropped: u64,
+        pub disconnected: bool,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum Push {
+        Delivered,
+        // il messaggio nuovo (DropNewest) o uno vecchio (DropOldest) è stato scartato
+        Dropped,
+        // il sottoscrittore non c'è più o è appena stato scollegato: va tolto dalla lista
+        Gone,
+    }
+
+    struct State<T> {
+        items: VecDeque<T>,
+        capacity: Option<usize>,
+        overflow: Overflow,
+        // chi pubblica non invierà più nulla
+        closed: bool,
+        receiver_alive: bool,
+        disconnected: bool,
+        delivered: u64,
+        dropped: u64,
+    }
+
+    pub struct Queue<T> {
+        id: u64,
+        state: Mutex<State<T>>,
+        not_empty: Condvar,
+        not_full: Condvar,
+    }
+
+    impl<T> Queue<T> {
+        // capacity None: coda illimitata
+        pub fn new(id: u64, capacity: Option<usize>, overflow: Overflow) -> Self {
+            assert!(capacity != Some(0), "capacity must be greater than zero");
+            Queue {
+                id,
+                state: Mutex::new(State {
+                    items: VecDeque::new(),
+                    capacity,
+                    overflow,
+                    closed: false,
+                    receiver_alive: true,
+                    disconnected: false,
+                    delivered: 0,
+                    dropped: 0,
+                }),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+            }
+        }
+
+        pub fn id(&self) -> u64 {
+            self.id
+        }
+
+        pub fn push(&self, item: T) -> Push {
+            let mut s = self.state.lock().unwrap();
+            let full = |s: &State<T>| s.capacity.is_some_and(|c| s.items.len() >= c);
+            if s.overflow == Overflow::Block {
+                s = self.not_full.wait_while(s, |s| full(s) && s.receiver_alive && !s.closed).unwrap();
+            }
+            if !s.receiver_alive || s.disconnected || s.closed {
+                return Push::Gone;
+            }
+
+            let mut res = Push::Delivered;
+            if full(&s) {
+                match s.overflow {
+                    Overflow::Block => unreachable!(),
+                    Overflow::DropNewest => {
+                        s.dropped += 1;
+                        return Push::Dropped;
+                    },
+                    Overflow::DropOldest => {
+                        s.items.pop_front();
+                        s.dropped += 1;
+                        res = Push::Dropped;
+                    },
+                    Overflow::Disconnect => {
+                        s.disconnected = true;
+                        s.dropped += 1;
+                        self.not_empty.notify_all();
+                        return Push::Gone;
+                    },
+                }
+            }
+            s.items.push_back(item);
+            s.delivered += 1;
+            self.not_empty.notify_one();
+            res
+        }
+
+        // bloccante: None quando la coda è vuota e non arriveranno altri messaggi
+        pub fn pop(&self) -> Option<T> {
+            let s = self.state.lock().unwrap();
+            let mut s = self.not_empty.wait_while(s, |s| s.items.is_empty() && !s.closed && !s.disconnected).unwrap();
+            let item = s.items.pop_front();
+            self.not_full.notify_one();
+            item
+        }
+
+        pub fn try_pop(&self) -> Option<T> {
+            let mut s = self.state.lock().unwrap();
+            let item = s.items.pop_front();
+            self.not_full.notify_one();
+            item
+        }
+
+        // lato di chi pubblica: dopo la close, pop restituisce i messaggi rimasti e poi None
+        pub fn close(&self) {
+            self.state.lock().unwrap().closed = true;
+            self.not_empty.notify_all();
+            self.not_full.notify_all();
+        }
+
+        // lato del sottoscrittore: sblocca chi pubblica con Overflow::Block
+        pub fn drop_receiver(&self) {
+            let mut s = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            s.receiver_alive = false;
+            s.items.clear();
+            self.not_full.notify_all();
+        }
+
+        pub fn metrics(&self) -> Metrics {
+            let s = self.state.lock().unwrap();
+            Metrics { id: self.id, lag: s.items.len(), delivered: s.delivered, dropped: s.dropped, disconnected: s.disconnected }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Duration;
+
+        fn drain(q: &Queue<u32>) -> Vec<u32> {
+            std::iter::from_fn(|| q.try_pop()).collect()
+        }
+
+        #[test]
+        fn test_drop_newest_and_oldest() {
+            let newest = Queue::new(0, Some(2), Overflow::DropNewest);
+            let oldest = Queue::new(1, Some(2), Overflow::DropOldest);
+            for i in 1..=4 {
+                newest.push(i);
+                oldest.push(i);
+            }
+            assert_eq!(drain(&newest), vec![1, 2]);
+            assert_eq!(drain(&oldest), vec![3, 4]);
+            assert_eq!(oldest.metrics(), Metrics { id: 1, lag: 0, delivered: 4, dropped: 2, disconnected: false });
+        }
+
+        #[test]
+        fn test_disconnect_slow_receiver() {
+            let q = Queue::new(0, Some(2), Overflow::Disconnect);
+            assert_eq!(q.push(1), Push::Delivered);
+            assert_eq!(q.push(2), Push::Delivered);
+            assert_eq!(q.push(3), Push::Gone);
+            assert_eq!(q.push(4), Push::Gone);
+            // i messaggi già in coda restano leggibili
+            assert_eq!((q.pop(), q.pop(), q.pop()), (Some(1), Some(2), None));
+            assert!(q.metrics().disconnected);
+        }
+
+        #[test]
+        fn test_block_waits_for_reader() {
+            let q = Arc::new(Queue::new(0, Some(1), Overflow::Block));
+            q.push(1);
+            let q2 = Arc::clone(&q);
+            let publisher = thread::spawn(move || q2.push(2));
+            thread::sleep(Duration::from_millis(50));
+            assert!(!publisher.is_finished());
+            assert_eq!(q.pop(), Some(1));
+            assert_eq!(publisher.join().unwrap(), Push::Delivered);
+            assert_eq!(q.pop(), Some(2));
+
+            // un sottoscrittore che sparisce sblocca chi pubblica
+            q.push(3);
+            let q2 = Arc::clone(&q);
+            let publisher = thread::spawn(move || q2.push(4));
+            thread::sleep(Duration::from_millis(20));
+            q.drop_receiver();
+            assert_eq!(publisher.join().unwrap(), Push::Gone);
+        }
+    }
+}
 
 pub mod dispatcher {
-    use std::sync::{self, mpsc::{self, Receiver, Sender}};
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex, Weak};
+    use crate::bounded::{Metrics, Overflow, Push, Queue};
+    use crate::topic::{PatternError, TopicTrie};
+
+    type MetricsHook = Arc<dyn Fn(&Metrics) + Send + Sync + 'static>;
+    // ogni messaggio viaggia con il suo numero di sequenza
+    type Entry<Msg> = (u64, Msg);
+    type SubQueue<Msg> = Arc<Queue<Entry<Msg>>>;
+
+    struct Inner<Msg> {
+        next_id: u64,
+        // coda di ogni sottoscrizione con il pattern con cui è registrata
+        queues: HashMap<u64, (String, SubQueue<Msg>)>,
+        topics: TopicTrie,
+        on_metrics: Option<MetricsHook>,
+        closed: bool,
+        next_seq: u64,
+        // ultimi messaggi inviati, con il topic (None per i broadcast), per chi si iscrive in ritardo
+        replay: VecDeque<(u64, Option<String>, Msg)>,
+        replay_capacity: usize,
+    }
+
+    impl<Msg> Inner<Msg> {
+        fn remove(&mut self, id: u64) -> bool {
+            match self.queues.remove(&id) {
+                Some((pattern, _)) => {
+                    self.topics.remove(&pattern, id);
+                    true
+                },
+                None => false,
+            }
+        }
+
+        // assegna il numero di sequenza e conserva il messaggio nel buffer di replay
+        fn record(&mut self, topic: Option<&str>, msg: &Msg) -> u64 where Msg: Clone {
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            if self.replay_capacity > 0 {
+                if self.replay.len() == self.replay_capacity {
+                    self.replay.pop_front();
+                }
+                self.replay.push_back((seq, topic.map(str::to_string), msg.clone()));
+            }
+            seq
+        }
+    }
 
     pub struct Dispatcher<Msg: Clone> {
-        senders: Vec<Sender<Msg>>,
+        inner: Arc<Mutex<Inner<Msg>>>,
     }
 
     pub struct Subscriber<Msg: Clone> {
-       receiver: Receiver<Msg>,
+       receiver: SubQueue<Msg>,
+       // il dispatcher può essere già stato distrutto: la sottoscrizione non lo tiene in vita
+       dispatcher: Weak<Mutex<Inner<Msg>>>,
+    }
+
+    impl<Msg: Clone> Default for Dispatcher<Msg> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<Msg: Clone> Drop for Dispatcher<Msg> {
+        fn drop(&mut self) {
+            self.close();
+        }
     }
 
     impl<Msg: Clone> Dispatcher<Msg> {
         pub fn new() -> Self {
             Self {
-                senders: Vec::<Sender<Msg>>::new(),
+                inner: Arc::new(Mutex::new(Inner {
+                    next_id: 0,
+                    queues: HashMap::new(),
+                    topics: TopicTrie::new(),
+                    on_metrics: None,
+                    closed: false,
+                    next_seq: 0,
+                    replay: VecDeque::new(),
+                    replay_capacity: 0,
+                })),
             }
         }
 
-        pub fn dispatch(&self, msg: Msg){
-            for s in (0..self.senders.len()) {
-                self.senders[s].send(msg.clone()).unwrap();
+        // conserva gli ultimi `capacity` messaggi per subscribe_from
+        pub fn with_replay(self, capacity: usize) -> Self {
+            self.inner.lock().unwrap().replay_capacity = capacity;
+            self
+        }
+
+        // numero di sequenza che riceverà il prossimo messaggio: subscribe_from(next_seq() - n)
+        // riceve almeno gli ultimi n messaggi, se sono ancora nel buffer
+        pub fn next_seq(&self) -> u64 {
+            self.inner.lock().unwrap().next_seq
+        }
+
+        // `hook` riceve le metriche di ogni sottoscrizione raggiunta da un dispatch
+        pub fn on_metrics<C>(self, hook: C) -> Self where C: Fn(&Metrics) + Send + Sync + 'static {
+            self.inner.lock().unwrap().on_metrics = Some(Arc::new(hook));
+            self
+        }
+
+        pub fn subscriber_count(&self) -> usize {
+            self.inner.lock().unwrap().queues.len()
+        }
+
+        // le sottoscrizioni leggono i messaggi rimasti e poi ricevono None; i dispatch successivi
+        // vengono ignorati e le nuove sottoscrizioni nascono già chiuse
+        pub fn close(&self) {
+            let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+            inner.closed = true;
+            inner.topics = TopicTrie::new();
+            for (_, q) in std::mem::take(&mut inner.queues).into_values() {
+                q.close();
             }
         }
 
-        pub fn subscribe(&mut self) -> Subscriber<Msg> {
-            let (tx, rx) = mpsc::channel::<Msg>();
-            self.senders.push(tx);
-            Subscriber { receiver: rx }
+        pub fn metrics(&self) -> Vec<Metrics> {
+            let inner = self.inner.lock().unwrap();
+            let mut m: Vec<Metrics> = inner.queues.values().map(|(_, q)| q.metrics()).collect();
+            m.sort_by_key(|m| m.id);
+            m
         }
-    }
 
-    impl<Msg: Clone> Subscriber<Msg> {
-        pub fn read(&self) -> Option<Msg> {
-            match self.receiver.recv() {
-                Ok(msg) => {
-                    return  Some(msg);
-                }, 
-                Err(_) => {
+        // broadcast: il messaggio arriva a tutte le sottoscrizioni, qualunque sia il loro pattern;
+        // restituisce il numero di sequenza assegnato al messaggio, None dopo la close
+        pub fn dispatch(&self, msg: Msg) -> Option<u64> {
+            let (seq, targets) = {
+                let mut inner = self.inner.lock().unwrap();
+                // un dispatcher chiuso non consuma sequenze e non riempie il buffer di replay
+                if inner.closed {
                     return None;
                 }
+                let seq = inner.record(None, &msg);
+                (seq, inner.queues.values().map(|(_, q)| Arc::clone(q)).collect())
+            };
+            self.deliver(targets, seq, msg);
+            Some(seq)
+        }
+
+        // il messaggio arriva solo alle sottoscrizioni il cui pattern corrisponde a `topic`
+        pub fn dispatch_to(&self, topic: &str, msg: Msg) -> Option<u64> {
+            let (seq, targets) = {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.closed {
+                    return None;
+                }
+                let seq = inner.record(Some(topic), &msg);
+                (seq, inner.topics.matches(topic).iter().filter_map(|id| inner.queues.get(id)).map(|(_, q)| Arc::clone(q)).collect())
+            };
+            self.deliver(targets, seq, msg);
+            Some(seq)
+        }
+
+        // l'invio avviene senza tenere il lock: un sottoscrittore con Overflow::Block
+        // rallenta solo chi pubblica, non subscribe né gli altri dispatch
+        fn deliver(&self, targets: Vec<SubQueue<Msg>>, seq: u64, msg: Msg) {
+            let hook = self.inner.lock().unwrap().on_metrics.clone();
+            let mut gone = Vec::new();
+            for q in targets {
+                if q.push((seq, msg.clone())) == Push::Gone {
+                    gone.push(q.id());
+                }
+                if let Some(hook) = &hook {
+                    hook(&q.metrics());
+                }
+            }
+
+            if !gone.is_empty() {
+                let mut inner = self.inner.lock().unwrap();
+                for id in gone {
+                    inner.remove(id);
+                }
             }
         }
+
+        // riceve tutti i messaggi, equivale a subscribe_topic("#")
+        pub fn subscribe(&self) -> Subscriber<Msg> {
+            self.subscribe_topic("#").unwrap()
+        }
+
+        // vedi topic per la sintassi dei pattern
+        pub fn subscribe_topic(&self, pattern: &str) -> Result<Subscriber<Msg>, PatternError> {
+            self.register(pattern, None, Overflow::Block, None)
+        }
+
+        // sottoscrizione con al massimo `capacity` messaggi non letti; oltre si applica `overflow`
+        pub fn subscribe_bounded(&self, pattern: &str, capacity: usize, overflow: Overflow) -> Result<Subscriber<Msg>, PatternError> {
+            self.register(pattern, Some(capacity), overflow, None)
+        }
+
+        // riceve prima i messaggi con sequenza >= `seq` ancora presenti nel buffer di replay
+        // (vedi with_replay), poi tutti i successivi, senza buchi né duplicati
+        pub fn subscribe_from(&self, seq: u64) -> Subscriber<Msg> {
+            self.subscribe_topic_from("#", seq).unwrap()
+        }
+
+        pub fn subscribe_topic_from(&self, pattern: &str, seq: u64) -> Result<Subscriber<Msg>, PatternError> {
+            self.register(pattern, None, Overflow::Block, Some(seq))
+        }
+
+        fn register(&self, pattern: &str, capacity: Option<usize>, overflow: Overflow, from: Option<u64>) -> Result<Subscriber<Msg>, PatternError> {
+            let mut inner = self.inner.lock().unwrap();
+            let id = inner.next_id;
+            let q = Arc::new(Queue::new(id, capacity, overflow));
+            let dispatcher = Arc::downgrade(&self.inner);
+            if inner.closed {
+                crate::topic::validate(pattern)?;
+                q.close();
+                return Ok(Subscriber { receiver: q, dispatcher });
+            }
+            inner.topics.insert(pattern, id)?;
+            inner.next_id += 1;
+            inner.queues.insert(id, (pattern.to_string(), Arc::clone(&q)));
+
+            // il replay avviene sotto il lock: un dispatch concorrente o è già nel buffer
+            // o raggiungerà la nuova coda, mai entrambe le cose
+            if let Some(from) = from {
+                let mut filter = TopicTrie::new();
+                filter.insert(pattern, id)?;
+                for (seq, topic, msg) in inner.replay.iter().filter(|(seq, _, _)| *seq >= from) {
+                    if topic.as_deref().is_none_or(|t| !filter.matches(t).is_empty()) {
+                        q.push((*seq, msg.clone()));
+                    }
+                }
+            }
+            Ok(Subscriber { receiver: q, dispatcher })
+        }
     }
-}
 
+    // la sottoscrizione viene tolta subito dal dispatcher, senza aspettare il prossimo dispatch
+    impl<Msg: Clone> Drop for Subscriber<Msg> {
+        fn drop(&mut self) {
+            if let Some(inner) = self.dispatcher.upgrade() {
+                inner.lock().unwrap_or_else(|e| e.into_inner()).remove(self.receiver.id());
+            }
+            self.receiver.drop_receiver();
+        }
+    }
+
+    impl<Msg: Clone> Subscriber<Msg> {
+        pub fn id(&self) -> u64 {
+            self.receiver.id()
+        }
+
+        pub fn read(&self) -> Option<Msg> {
+            self.receiver.pop().map(|(_, msg)| msg)
+        }
+
+        // come read, con il numero di sequenza del messaggio
+        pub fn read_with_seq(&self) -> Option<(u64, Msg)> {
+            self.receiver.pop()
+        }
+
+        // equivale al drop della sottoscrizione
+        pub fn unsubscribe(self) {}
+    }
+}
 
 use std::thread;
 use std::time::Duration;
 
-use dispatcher::{Dispatcher, Subscriber};
+use dispatcher::Dispatcher;
 
 fn main() {
-    let mut dispatcher = Dispatcher::new();
+    let dispatcher = Dispatcher::new();
 
     // Due sottoscrittori
     let sub1 = dispatcher.subscribe();
@@ -90,3 +711,173 @@ fn main() {
     h2.join().unwrap();
 }
 
+#[cfg(test)]
+mod tests {
+    use super::bounded::{Metrics, Overflow};
+    use super::dispatcher::Dispatcher;
+    use super::topic::PatternError;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_dispatch_to_reaches_only_matching_subscribers() {
+        let d = Dispatcher::new();
+        let kitchen = d.subscribe_topic("sensors.kitchen.temp").unwrap();
+        let temps = d.subscribe_topic("sensors.*.temp").unwrap();
+        let sensors = d.subscribe_topic("sensors.#").unwrap();
+        let all = d.subscribe();
+
+        d.dispatch_to("sensors.kitchen.temp", 1);
+        d.dispatch_to("sensors.garage.temp", 2);
+        d.dispatch_to("sensors.garage.humidity", 3);
+        d.dispatch_to("alerts.fire", 4);
+        d.dispatch(5);
+        drop(d);
+
+        let read_all = |s: super::dispatcher::Subscriber<i32>| std::iter::from_fn(move || s.read()).collect::<Vec<_>>();
+        assert_eq!(read_all(kitchen), vec![1, 5]);
+        assert_eq!(read_all(temps), vec![1, 2, 5]);
+        assert_eq!(read_all(sensors), vec![1, 2, 3, 5]);
+        assert_eq!(read_all(all), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let d = Dispatcher::<i32>::new();
+        assert!(matches!(d.subscribe_topic("a.#.b"), Err(PatternError::MisplacedHash)));
+        assert!(matches!(d.subscribe_topic("a..b"), Err(PatternError::EmptySegment)));
+    }
+
+    #[test]
+    fn test_bounded_subscribers_and_metrics() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = Arc::clone(&seen);
+        let d = Dispatcher::new().on_metrics(move |m: &Metrics| s.lock().unwrap().push(m.clone()));
+        let newest = d.subscribe_bounded("#", 2, Overflow::DropNewest).unwrap();
+        let oldest = d.subscribe_bounded("#", 2, Overflow::DropOldest).unwrap();
+        let slow = d.subscribe_bounded("#", 2, Overflow::Disconnect).unwrap();
+
+        for i in 1..=4 {
+            d.dispatch(i);
+        }
+        // il sottoscrittore scollegato non riceve più nulla e viene tolto dal dispatcher
+        assert_eq!(d.metrics().iter().map(|m| m.id).collect::<Vec<_>>(), vec![newest.id(), oldest.id()]);
+        let m = d.metrics();
+        assert_eq!((m[0].lag, m[0].dropped), (2, 2));
+        assert_eq!((m[1].lag, m[1].dropped), (2, 2));
+
+        let last_slow = seen.lock().unwrap().iter().rev().find(|m| m.id == slow.id()).cloned().unwrap();
+        assert!(last_slow.disconnected);
+        drop(d);
+
+        assert_eq!(std::iter::from_fn(|| newest.read()).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(std::iter::from_fn(|| oldest.read()).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(std::iter::from_fn(|| slow.read()).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_block_policy_slows_only_the_publisher() {
+        let d = Arc::new(Dispatcher::new());
+        let sub = d.subscribe_bounded("#", 1, Overflow::Block).unwrap();
+        d.dispatch(1);
+
+        let d2 = Arc::clone(&d);
+        let publisher = thread::spawn(move || d2.dispatch(2));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!publisher.is_finished());
+        // il publisher bloccato non tiene il lock del dispatcher
+        let other = d.subscribe();
+
+        assert_eq!(sub.read(), Some(1));
+        publisher.join().unwrap();
+        assert_eq!(sub.read(), Some(2));
+        d.dispatch(3);
+        assert_eq!(other.read(), Some(3));
+    }
+
+    #[test]
+    fn test_subscriber_lifecycle() {
+        let d = Dispatcher::new();
+        let a = d.subscribe();
+        let b = d.subscribe_topic("x.y").unwrap();
+        assert_eq!(d.subscriber_count(), 2);
+        drop(a);
+        assert_eq!(d.subscriber_count(), 1);
+        b.unsubscribe();
+        assert_eq!(d.subscriber_count(), 0);
+
+        let c = d.subscribe();
+        d.dispatch(1);
+        d.close();
+        assert_eq!(d.dispatch(2), None);
+        assert_eq!(d.dispatch_to("x.y", 3), None);
+        assert_eq!(d.next_seq(), 1);
+        assert_eq!(d.subscriber_count(), 0);
+        assert_eq!(c.read(), Some(1));
+        assert_eq!(c.read(), None);
+        // dopo la close le nuove sottoscrizioni sono già chiuse
+        assert_eq!(d.subscribe().read(), None);
+    }
+
+    #[test]
+    fn test_concurrent_subscribe_dispatch_drop() {
+        let d = Arc::new(Dispatcher::new());
+        let keep = d.subscribe();
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let d = Arc::clone(&d);
+            handles.push(thread::spawn(move || {
+                for _ in 0..200 {
+                    let s = d.subscribe();
+                    d.dispatch(0);
+                    assert_eq!(s.read(), Some(0));
+                }
+            }));
+        }
+        for _ in 0..4 {
+            let d = Arc::clone(&d);
+            handles.push(thread::spawn(move || for i in 0..200 { d.dispatch(i); }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(d.subscriber_count(), 1);
+        d.close();
+        assert_eq!(std::iter::from_fn(|| keep.read()).count(), 4 * 200 + 4 * 200);
+    }
+
+    #[test]
+    fn test_subscribe_from_replays_missed_messages() {
+        let d = Dispatcher::new().with_replay(3);
+        assert_eq!(d.dispatch(0), Some(0));
+        d.dispatch_to("a.x", 1);
+        d.dispatch_to("b.x", 2);
+        d.dispatch(3);
+        assert_eq!(d.next_seq(), 4);
+
+        // la sequenza 0 è già uscita dal buffer
+        let all = d.subscribe_from(0);
+        let a = d.subscribe_topic_from("a.*", 1).unwrap();
+        let late = d.subscribe_from(d.next_seq() - 1);
+        d.dispatch_to("a.y", 4);
+        d.close();
+
+        assert_eq!(std::iter::from_fn(|| all.read_with_seq()).collect::<Vec<_>>(), vec![(1, 1), (2, 2), (3, 3), (4, 4)]);
+        assert_eq!(std::iter::from_fn(|| a.read()).collect::<Vec<_>>(), vec![1, 3, 4]);
+        assert_eq!(std::iter::from_fn(|| late.read()).collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_subscribe_from_during_dispatch_has_no_gaps() {
+        let d = Arc::new(Dispatcher::new().with_replay(1000));
+        let d2 = Arc::clone(&d);
+        let publisher = thread::spawn(move || for i in 0..500 { d2.dispatch(i); });
+        thread::sleep(Duration::from_millis(1));
+        let s = d.subscribe_from(0);
+        publisher.join().unwrap();
+        d.close();
+        let seqs: Vec<u64> = std::iter::from_fn(|| s.read_with_seq()).map(|(seq, _)| seq).collect();
+        assert_eq!(seqs, (0..500).collect::<Vec<_>>());
+    }
+}
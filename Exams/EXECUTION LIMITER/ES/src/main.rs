@@ -1,45 +1,383 @@
 
 pub mod limiter {
+    use std::collections::VecDeque;
+    use std::fmt;
     use std::sync::{Arc, Condvar, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug, PartialEq)]
+    pub enum LimiterError {
+        // f ha restituito errore
+        Failed,
+        // nessun permesso si è liberato entro il timeout
+        Timeout,
+        // try_execute/try_acquire: nessun permesso libero subito
+        WouldBlock,
+    }
+
+    impl fmt::Display for LimiterError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                LimiterError::Failed => write!(f, "function failed"),
+                LimiterError::Timeout => write!(f, "timed out waiting for permit"),
+                LimiterError::WouldBlock => write!(f, "no permit available"),
+            }
+        }
+    }
+
+    struct State {
+        // può cambiare a runtime con set_limit
+        limit: usize,
+        in_use: usize,
+        // biglietti dei thread in attesa, in ordine di arrivo: entra solo il primo della coda
+        waiting: VecDeque<u64>,
+        next_ticket: u64,
+    }
 
     pub struct ExecutionLimiter {
-        count: Arc<Mutex<usize>>,
-        condvar: Arc<Condvar>,
-        nthreads: usize,
+        counter: Arc<(Mutex<State>, Condvar)>,
+    }
+
+    // permesso ottenuto con acquire: viene restituito al drop, anche se f va in panic
+    pub struct Permit<'a> {
+        limiter: &'a ExecutionLimiter,
+        weight: usize,
     }
 
     impl ExecutionLimiter {
         pub fn new(n: usize) -> Self {
+            assert!(n > 0, "limit must be greater than zero");
             Self {
-                count: Arc::new(Mutex::new(0)),
-                condvar: Arc::new(Condvar::new()),
-                nthreads: n,
+                counter: Arc::new((Mutex::new(State { limit: n, in_use: 0, waiting: VecDeque::new(), next_ticket: 0 }), Condvar::new())),
+            }
+        }
+
+        pub fn limit(&self) -> usize {
+            self.counter.0.lock().unwrap().limit
+        }
+
+        // riducendo il limite le esecuzioni in corso terminano normalmente, ma nessuno entra
+        // finché non si scende sotto la nuova soglia; aumentandolo vengono svegliati i thread in coda
+        pub fn set_limit(&self, n: usize) {
+            assert!(n > 0, "limit must be greater than zero");
+            let (lock, condvar) = &*self.counter;
+            lock.lock().unwrap().limit = n;
+            condvar.notify_all();
+        }
+
+        // attende un permesso senza consumare CPU, in ordine di arrivo
+        pub fn execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            self.execute_weighted(1, f)
+        }
+
+        // occupa `weight` permessi insieme; vedi acquire_weighted
+        pub fn execute_weighted<F, R>(&self, weight: usize, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.acquire_weighted(weight);
+            f().map_err(|_| LimiterError::Failed)
+        }
+
+        pub fn execute_timeout<F, R>(&self, timeout: Duration, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.acquire_timeout(timeout)?;
+            f().map_err(|_| LimiterError::Failed)
+        }
+
+        // non attende: WouldBlock se non c'è un permesso libero o qualcuno è già in coda
+        pub fn try_execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.try_acquire()?;
+            f().map_err(|_| LimiterError::Failed)
+        }
+
+        pub fn acquire(&self) -> Permit<'_> {
+            self.acquire_weighted(1)
+        }
+
+        // chi chiede molti permessi non viene scavalcato da chi ne chiede pochi: la coda è FIFO.
+        // Un peso maggiore del limite viene eseguito da solo, quando non c'è nient'altro in corso
+        pub fn acquire_weighted(&self, weight: usize) -> Permit<'_> {
+            match self.acquire_until(weight, None) {
+                Ok(permit) => permit,
+                Err(_) => unreachable!(),
             }
         }
 
-        pub fn execute<F, R>(&self, f: F) -> R where F: Fn() -> Result<R, ()> + Send + Sync + 'static,
-        R: Send + Sync + 'static {
-            let mut cnt = self.count.lock().unwrap();
+        pub fn acquire_timeout(&self, timeout: Duration) -> Result<Permit<'_>, LimiterError> {
+            self.acquire_until(1, Some(Instant::now() + timeout))
+        }
 
-            *cnt += 1;
-            if *cnt == self.nthreads {
-                cnt = self.condvar.wait_while(cnt, |c| *c == self.nthreads).unwrap();
+        pub fn try_acquire(&self) -> Result<Permit<'_>, LimiterError> {
+            let (lock, _) = &*self.counter;
+            let mut s = lock.lock().unwrap();
+            if !s.waiting.is_empty() || !s.fits(1) {
+                return Err(LimiterError::WouldBlock);
             }
+            s.in_use += 1;
+            Ok(Permit { limiter: self, weight: 1 })
+        }
 
-            drop(cnt);
-            match f() {
-                Ok(r) => {
-                    self.condvar.notify_all();
-                    return r;
-                },
-                Err(_) => {
-                    panic!("Error in function f");
+        // esecuzioni in corso
+        pub fn running(&self) -> usize {
+            self.counter.0.lock().unwrap().in_use
+        }
+
+        fn acquire_until(&self, weight: usize, deadline: Option<Instant>) -> Result<Permit<'_>, LimiterError> {
+            assert!(weight > 0, "weight must be greater than zero");
+            let (lock, condvar) = &*self.counter;
+            let mut s = lock.lock().unwrap();
+            let ticket = s.next_ticket;
+            s.next_ticket += 1;
+            s.waiting.push_back(ticket);
+
+            loop {
+                if s.waiting.front() == Some(&ticket) && s.fits(weight) {
+                    s.waiting.pop_front();
+                    s.in_use += weight;
+                    // il prossimo in coda potrebbe trovare altri permessi liberi
+                    condvar.notify_all();
+                    return Ok(Permit { limiter: self, weight });
+                }
+                match deadline {
+                    None => s = condvar.wait(s).unwrap(),
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            // chi era dietro non deve restare bloccato dal biglietto abbandonato
+                            s.waiting.retain(|t| *t != ticket);
+                            condvar.notify_all();
+                            return Err(LimiterError::Timeout);
+                        }
+                        s = condvar.wait_timeout(s, deadline - now).unwrap().0;
+                    },
                 }
             }
         }
+
+        fn release(&self, weight: usize) {
+            let (lock, condvar) = &*self.counter;
+            lock.lock().unwrap_or_else(|e| e.into_inner()).in_use -= weight;
+            condvar.notify_all();
+        }
+    }
+
+    impl State {
+        fn fits(&self, weight: usize) -> bool {
+            self.in_use + weight <= self.limit || self.in_use == 0
+        }
+    }
+
+    impl Permit<'_> {
+        pub fn weight(&self) -> usize {
+            self.weight
+        }
+
+        // equivale al drop del permesso
+        pub fn release(self) {}
+    }
+
+    impl Drop for Permit<'_> {
+        fn drop(&mut self) {
+            self.limiter.release(self.weight);
+        }
+    }
+}
+
+pub mod clock {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // sorgente del tempo per i rate limiter: nei test si usa MockClock e il tempo avanza a mano
+    pub trait Clock: Send + Sync {
+        fn now(&self) -> Instant;
+
+        fn sleep(&self, d: Duration);
+    }
+
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn sleep(&self, d: Duration) {
+            thread::sleep(d);
+        }
+    }
+
+    pub struct MockClock {
+        now: Mutex<Instant>,
+    }
+
+    impl MockClock {
+        pub fn new() -> Arc<Self> {
+            Arc::new(MockClock { now: Mutex::new(Instant::now()) })
+        }
+
+        pub fn advance(&self, d: Duration) {
+            *self.now.lock().unwrap() += d;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        // chi attende fa avanzare il tempo finto invece di dormire davvero
+        fn sleep(&self, d: Duration) {
+            self.advance(d);
+        }
     }
 }
 
+pub mod rate {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use crate::clock::{Clock, SystemClock};
+    use crate::limiter::{ExecutionLimiter, LimiterError};
+
+    // limite di throughput: stessa forma di execute/try_execute di ExecutionLimiter
+    pub trait RateLimit: Send + Sync {
+        fn clock(&self) -> &dyn Clock;
+
+        // registra un'esecuzione se è consentita adesso, altrimenti dice quanto manca
+        fn try_acquire(&self) -> Result<(), Duration>;
+
+        fn acquire(&self) {
+            while let Err(wait) = self.try_acquire() {
+                self.clock().sleep(wait);
+            }
+        }
+
+        // attende (senza consumare CPU) che il limite consenta una nuova esecuzione
+        fn execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()>, Self: Sized {
+            self.acquire();
+            f().map_err(|_| LimiterError::Failed)
+        }
+
+        fn try_execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()>, Self: Sized {
+            self.try_acquire().map_err(|_| LimiterError::WouldBlock)?;
+            f().map_err(|_| LimiterError::Failed)
+        }
+    }
+
+    struct Bucket {
+        tokens: f64,
+        last: Instant,
+    }
+
+    // `per_second` gettoni al secondo, accumulabili fino a `burst`
+    pub struct TokenBucket {
+        clock: Arc<dyn Clock>,
+        bucket: Mutex<Bucket>,
+        per_second: f64,
+        burst: f64,
+    }
+
+    impl TokenBucket {
+        pub fn new(per_second: u32, burst: u32) -> Self {
+            assert!(per_second > 0 && burst > 0, "rate and burst must be greater than zero");
+            let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+            Self {
+                bucket: Mutex::new(Bucket { tokens: burst as f64, last: clock.now() }),
+                clock,
+                per_second: per_second as f64,
+                burst: burst as f64,
+            }
+        }
+
+        pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+            self.bucket.get_mut().unwrap().last = clock.now();
+            self.clock = clock;
+            self
+        }
+    }
+
+    impl RateLimit for TokenBucket {
+        fn clock(&self) -> &dyn Clock {
+            &*self.clock
+        }
+
+        fn try_acquire(&self) -> Result<(), Duration> {
+            let now = self.clock.now();
+            let mut b = self.bucket.lock().unwrap();
+            let elapsed = now.saturating_duration_since(b.last).as_secs_f64();
+            b.tokens = (b.tokens + elapsed * self.per_second).min(self.burst);
+            b.last = now;
+            if b.tokens >= 1.0 {
+                b.tokens -= 1.0;
+                Ok(())
+            } else {
+                Err(Duration::from_secs_f64((1.0 - b.tokens) / self.per_second))
+            }
+        }
+    }
+
+    // al massimo `max` esecuzioni in ogni intervallo lungo `window`
+    pub struct SlidingWindow {
+        clock: Arc<dyn Clock>,
+        // istanti delle esecuzioni ancora dentro la finestra
+        hits: Mutex<VecDeque<Instant>>,
+        max: usize,
+        window: Duration,
+    }
+
+    impl SlidingWindow {
+        pub fn new(max: usize, window: Duration) -> Self {
+            assert!(max > 0, "max must be greater than zero");
+            Self { clock: Arc::new(SystemClock), hits: Mutex::new(VecDeque::new()), max, window }
+        }
+
+        pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+            self.clock = clock;
+            self
+        }
+    }
+
+    impl RateLimit for SlidingWindow {
+        fn clock(&self) -> &dyn Clock {
+            &*self.clock
+        }
+
+        fn try_acquire(&self) -> Result<(), Duration> {
+            let now = self.clock.now();
+            let mut hits = self.hits.lock().unwrap();
+            while hits.front().is_some_and(|t| now.saturating_duration_since(*t) >= self.window) {
+                hits.pop_front();
+            }
+            if hits.len() < self.max {
+                hits.push_back(now);
+                Ok(())
+            } else {
+                Err(hits[0] + self.window - now)
+            }
+        }
+    }
+
+    // limite di concorrenza e di throughput insieme: prima si ottiene il permesso, poi il gettone,
+    // così i gettoni vengono consumati solo da chi può partire subito
+    pub struct Limited<L: RateLimit> {
+        concurrency: ExecutionLimiter,
+        rate: L,
+    }
+
+    impl<L: RateLimit> Limited<L> {
+        pub fn new(concurrency: ExecutionLimiter, rate: L) -> Self {
+            Self { concurrency, rate }
+        }
+
+        pub fn execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.concurrency.acquire();
+            self.rate.execute(f)
+        }
+
+        pub fn try_execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.concurrency.try_acquire()?;
+            self.rate.try_execute(f)
+        }
+    }
+}
 
 use std::{
     sync::Arc,
@@ -64,7 +402,7 @@ fn main() {
                 thread::sleep(Duration::from_millis(200));
                 println!("[Task {i}] end");
                 Ok(i as i32 * i as i32)
-            });
+            }).unwrap();
             println!("[Task {i}] result = {res}");
         }));
     }
@@ -76,3 +414,202 @@ fn main() {
     println!("All tasks finished.");
 }
 
+#[cfg(test)]
+mod tests {
+    use super::clock::{Clock, MockClock};
+    use super::limiter::{ExecutionLimiter, LimiterError};
+    use super::rate::{Limited, RateLimit, SlidingWindow, TokenBucket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_at_most_n_concurrent_executions() {
+        let limiter = Arc::new(ExecutionLimiter::new(3));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..12).map(|i| {
+            let (limiter, running, peak) = (Arc::clone(&limiter), Arc::clone(&running), Arc::clone(&peak));
+            thread::spawn(move || limiter.execute(|| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                // anche le esecuzioni fallite restituiscono il permesso
+                if i % 2 == 0 { Ok(i) } else { Err(()) }
+            }))
+        }).collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(results.iter().filter(|r| **r == Err(LimiterError::Failed)).count(), 6);
+        assert_eq!(limiter.running(), 0);
+    }
+
+    #[test]
+    fn test_fifo_admission() {
+        let limiter = Arc::new(ExecutionLimiter::new(1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let permit = limiter.acquire();
+        let handles: Vec<_> = (0..5).map(|i| {
+            let (limiter, order) = (Arc::clone(&limiter), Arc::clone(&order));
+            let h = thread::spawn(move || limiter.execute(|| {
+                order.lock().unwrap().push(i);
+                Ok(())
+            }));
+            // ogni thread si mette in coda prima del successivo
+            thread::sleep(Duration::from_millis(20));
+            h
+        }).collect();
+        permit.release();
+        for h in handles {
+            h.join().unwrap().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_timeout_and_try_execute() {
+        let limiter = Arc::new(ExecutionLimiter::new(1));
+        let permit = limiter.acquire();
+        assert_eq!(limiter.try_execute(|| Ok(1)), Err(LimiterError::WouldBlock));
+        assert_eq!(limiter.execute_timeout(Duration::from_millis(20), || Ok(1)), Err(LimiterError::Timeout));
+        assert_eq!(LimiterError::Timeout.to_string(), "timed out waiting for permit");
+
+        // un waiter scaduto non blocca chi era in coda dietro di lui
+        let l2 = Arc::clone(&limiter);
+        let expired = thread::spawn(move || l2.execute_timeout(Duration::from_millis(30), || Ok(1)));
+        thread::sleep(Duration::from_millis(10));
+        let l2 = Arc::clone(&limiter);
+        let patient = thread::spawn(move || l2.execute_timeout(Duration::from_secs(5), || Ok(2)));
+        assert_eq!(expired.join().unwrap(), Err(LimiterError::Timeout));
+        drop(permit);
+        assert_eq!(patient.join().unwrap(), Ok(2));
+        assert_eq!(limiter.try_execute(|| Ok(3)), Ok(3));
+    }
+
+    #[test]
+    fn test_panic_releases_permit() {
+        let limiter = Arc::new(ExecutionLimiter::new(1));
+        let l2 = Arc::clone(&limiter);
+        assert!(thread::spawn(move || l2.execute(|| -> Result<(), ()> { panic!("boom") })).join().is_err());
+        assert_eq!(limiter.running(), 0);
+        assert_eq!(limiter.try_execute(|| Ok(1)), Ok(1));
+    }
+
+    #[test]
+    fn test_weighted_request_is_not_starved() {
+        let limiter = Arc::new(ExecutionLimiter::new(3));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let a = limiter.acquire();
+        let b = limiter.acquire();
+
+        let (l2, o2) = (Arc::clone(&limiter), Arc::clone(&order));
+        let heavy = thread::spawn(move || l2.execute_weighted(3, || {
+            o2.lock().unwrap().push("heavy");
+            Ok(())
+        }));
+        thread::sleep(Duration::from_millis(20));
+        // c'è un permesso libero, ma il lavoro pesante è arrivato prima
+        let (l2, o2) = (Arc::clone(&limiter), Arc::clone(&order));
+        let light = thread::spawn(move || l2.execute(|| {
+            o2.lock().unwrap().push("light");
+            Ok(())
+        }));
+        thread::sleep(Duration::from_millis(20));
+        assert!(order.lock().unwrap().is_empty());
+
+        drop(a);
+        b.release();
+        heavy.join().unwrap().unwrap();
+        light.join().unwrap().unwrap();
+        assert_eq!(*order.lock().unwrap(), vec!["heavy", "light"]);
+
+        // un peso oltre il limite viene eseguito da solo
+        assert_eq!(limiter.execute_weighted(5, || Ok(limiter.running())), Ok(5));
+    }
+
+    #[test]
+    fn test_set_limit_at_runtime() {
+        let limiter = Arc::new(ExecutionLimiter::new(1));
+        let permit = limiter.acquire();
+        let handles: Vec<_> = (0..3).map(|_| {
+            let l2 = Arc::clone(&limiter);
+            thread::spawn(move || l2.execute(|| {
+                thread::sleep(Duration::from_millis(50));
+                Ok(())
+            }))
+        }).collect();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(limiter.running(), 1);
+
+        // i thread in coda vengono svegliati quando la capacità cresce
+        limiter.set_limit(4);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(limiter.running(), 4);
+
+        limiter.set_limit(1);
+        assert_eq!(limiter.limit(), 1);
+        drop(permit);
+        for h in handles {
+            h.join().unwrap().unwrap();
+        }
+        let _permit = limiter.acquire();
+        assert_eq!(limiter.try_execute(|| Ok(())), Err(LimiterError::WouldBlock));
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let clock = MockClock::new();
+        let bucket = TokenBucket::new(2, 2).with_clock(clock.clone());
+        let start = clock.now();
+        assert_eq!(bucket.try_execute(|| Ok(1)), Ok(1));
+        assert_eq!(bucket.try_execute(|| Ok(2)), Ok(2));
+        assert_eq!(bucket.try_execute(|| Ok(3)), Err(LimiterError::WouldBlock));
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(bucket.try_execute(|| Ok(4)), Ok(4));
+
+        // execute attende il prossimo gettone: con il MockClock il tempo avanza esattamente di 500ms
+        assert_eq!(bucket.execute(|| Ok(5)), Ok(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+        // il burst non cresce oltre la capacità
+        clock.advance(Duration::from_secs(10));
+        assert!(bucket.try_execute(|| Ok(())).is_ok());
+        assert!(bucket.try_execute(|| Ok(())).is_ok());
+        assert!(bucket.try_execute(|| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let clock = MockClock::new();
+        let window = SlidingWindow::new(3, Duration::from_secs(1)).with_clock(clock.clone());
+        let start = clock.now();
+        for i in 0..3 {
+            assert_eq!(window.try_execute(|| Ok(i)), Ok(i));
+            clock.advance(Duration::from_millis(200));
+        }
+        assert_eq!(window.try_execute(|| Ok(3)), Err(LimiterError::WouldBlock));
+        // la prima esecuzione esce dalla finestra a t = 1s
+        assert_eq!(window.execute(|| Err::<(), ()>(())), Err(LimiterError::Failed));
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+        assert_eq!(window.try_execute(|| Ok(4)), Err(LimiterError::WouldBlock));
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(window.try_execute(|| Ok(5)), Ok(5));
+    }
+
+    #[test]
+    fn test_concurrency_and_rate_together() {
+        let clock = MockClock::new();
+        let limited = Arc::new(Limited::new(ExecutionLimiter::new(1), TokenBucket::new(1, 1).with_clock(clock.clone())));
+        let start = clock.now();
+
+        // mentre un'esecuzione è in corso il permesso di concorrenza non è disponibile
+        let l2 = Arc::clone(&limited);
+        assert_eq!(limited.execute(|| Ok(l2.try_execute(|| Ok(0)))), Ok(Err(LimiterError::WouldBlock)));
+        assert_eq!(limited.try_execute(|| Ok(1)), Err(LimiterError::WouldBlock));
+        for i in 0..3 {
+            assert_eq!(limited.execute(|| Ok(i)), Ok(i));
+        }
+        assert_eq!(clock.now() - start, Duration::from_secs(3));
+    }
+}
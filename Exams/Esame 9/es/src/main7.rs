@@ -1,39 +1,453 @@
+pub mod bounded {
+    use std::collections::VecDeque;
+    use std::sync::{Condvar, Mutex};
+    use std::time::Instant;
+
+    // cosa fare quando la coda di un sottoscrittore è piena
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Overflow {
+        // chi pubblica attende che il sottoscrittore legga
+        Block,
+        // il nuovo messaggio viene scartato
+        DropNewest,
+        // viene scartato il messaggio più vecchio in coda
+        DropOldest,
+        // il sottoscrittore lento viene scollegato: legge quanto ha in coda e poi riceve None
+        Disconnect,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Metrics {
+        pub id: u64,
+        // messaggi in coda non ancora letti
+        pub lag: usize,
+        pub delivered: u64,
+        pub dropped: u64,
+        pub disconnected: bool,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum Push {
+        Delivered,
+        // il messaggio nuovo (DropNewest) o uno vecchio (DropOldest) è stato scartato
+        Dropped,
+        // il sottoscrittore non c'è più o è appena stato scollegato: va tolto dalla lista
+        Gone,
+    }
+
+    struct State<T> {
+        items: VecDeque<T>,
+        capacity: Option<usize>,
+        overflow: Overflow,
+        // chi pubblica non invierà più nulla
+        closed: bool,
+        receiver_alive: bool,
+        disconnected: bool,
+        delivered: u64,
+        dropped: u64,
+    }
+
+    pub struct Queue<T> {
+        id: u64,
+        state: Mutex<State<T>>,
+        not_empty: Condvar,
+        not_full: Condvar,
+    }
+
+    impl<T> Queue<T> {
+        // capacity None: coda illimitata
+        pub fn new(id: u64, capacity: Option<usize>, overflow: Overflow) -> Self {
+            assert!(capacity != Some(0), "capacity must be greater than zero");
+            Queue {
+                id,
+                state: Mutex::new(State {
+                    items: VecDeque::new(),
+                    capacity,
+                    overflow,
+                    closed: false,
+                    receiver_alive: true,
+                    disconnected: false,
+                    delivered: 0,
+                    dropped: 0,
+                }),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+            }
+        }
+
+        pub fn id(&self) -> u64 {
+            self.id
+        }
+
+        pub fn push(&self, item: T) -> Push {
+            match self.push_until(item, None) {
+                Ok(res) => res,
+                Err(_) => unreachable!(),
+            }
+        }
+
+        // con Overflow::Block attende al massimo fino a `deadline`: se la coda è ancora piena
+        // restituisce il valore e lo conta tra gli scartati
+        pub fn push_until(&self, item: T, deadline: Option<Instant>) -> Result<Push, T> {
+            let mut s = self.state.lock().unwrap();
+            let full = |s: &State<T>| s.capacity.is_some_and(|c| s.items.len() >= c);
+            let waiting = |s: &State<T>| full(s) && s.receiver_alive && !s.closed;
+            if s.overflow == Overflow::Block {
+                match deadline {
+                    None => s = self.not_full.wait_while(s, |s| waiting(s)).unwrap(),
+                    Some(deadline) => {
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        s = self.not_full.wait_timeout_while(s, timeout, |s| waiting(s)).unwrap().0;
+                        if waiting(&s) {
+                            s.dropped += 1;
+                            return Err(item);
+                        }
+                    },
+                }
+            }
+            if !s.receiver_alive || s.disconnected || s.closed {
+                return Ok(Push::Gone);
+            }
+
+            let mut res = Push::Delivered;
+            if full(&s) {
+                match s.overflow {
+                    Overflow::Block => unreachable!(),
+                    Overflow::DropNewest => {
+                        s.dropped += 1;
+                        return Ok(Push::Dropped);
+                    },
+                    Overflow::DropOldest => {
+                        s.items.pop_front();
+                        s.dropped += 1;
+                        res = Push::Dropped;
+                    },
+                    Overflow::Disconnect => {
+                        s.disconnected = true;
+                        s.dropped += 1;
+                        self.not_empty.notify_all();
+                        return Ok(Push::Gone);
+                    },
+                }
+            }
+            s.items.push_back(item);
+            s.delivered += 1;
+            self.not_empty.notify_one();
+            Ok(res)
+        }
+
+        // bloccante: None quando la coda è vuota e non arriveranno altri messaggi
+        pub fn pop(&self) -> Option<T> {
+            let s = self.state.lock().unwrap();
+            let mut s = self.not_empty.wait_while(s, |s| s.items.is_empty() && !s.closed && !s.disconnected).unwrap();
+            let item = s.items.pop_front();
+            self.not_full.notify_one();
+            item
+        }
+
+        pub fn try_pop(&self) -> Option<T> {
+            let mut s = self.state.lock().unwrap();
+            let item = s.items.pop_front();
+            self.not_full.notify_one();
+            item
+        }
+
+        // lato di chi pubblica: dopo la close, pop restituisce i messaggi rimasti e poi None
+        pub fn close(&self) {
+            self.state.lock().unwrap().closed = true;
+            self.not_empty.notify_all();
+            self.not_full.notify_all();
+        }
+
+        // lato del sottoscrittore: sblocca chi pubblica con Overflow::Block
+        pub fn drop_receiver(&self) {
+            let mut s = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            s.receiver_alive = false;
+            s.items.clear();
+            self.not_full.notify_all();
+        }
+
+        pub fn metrics(&self) -> Metrics {
+            let s = self.state.lock().unwrap();
+            Metrics { id: self.id, lag: s.items.len(), delivered: s.delivered, dropped: s.dropped, disconnected: s.disconnected }
+        }
+    }
+
+}
+
 
 pub mod channel {
-    use std::sync::{mpsc::{self, Receiver, SendError, Sender}, Arc, Condvar, Mutex};
+    use std::collections::VecDeque;
+    use std::sync::{mpsc::{RecvError, SendError, TrySendError}, Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use crate::bounded::{Metrics, Overflow, Push, Queue};
+
+    type MetricsHook = Arc<dyn Fn(&Metrics) + Send + Sync + 'static>;
+
+    type SeqQueue<T> = Arc<Queue<(u64, T)>>;
+
+    struct Inner<T> {
+        senders: Vec<SeqQueue<T>>,
+        next_seq: u64,
+        // ultimi valori accettati da almeno un ricevitore, per chi si iscrive in ritardo
+        replay: VecDeque<(u64, T)>,
+        replay_capacity: usize,
+        // invii in corso con le code di chi si è iscritto con subscribe_from nel frattempo:
+        // il valore arriva a queste code solo se l'invio va a buon fine
+        in_flight: Vec<(u64, Vec<SeqQueue<T>>)>,
+    }
+
+    pub struct MultiChannel<T: Clone + Send> {
+        inner: Arc<Mutex<Inner<T>>>,
+        next_id: Mutex<u64>,
+        // un invio alla volta: tutti i ricevitori vedono i valori nell'ordine delle sequenze
+        send_lock: Mutex<()>,
+        on_metrics: Option<MetricsHook>,
+    }
+
+    // ogni valore viaggia con il suo numero di sequenza
+    pub struct Receiver<T> {
+        queue: Arc<Queue<(u64, T)>>,
+    }
+
+    // esito di un invio andato a buon fine
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct Delivery {
+        pub seq: u64,
+        // ricevitori che hanno il valore in coda
+        pub delivered: usize,
+        // ricevitori che hanno perso un valore per la loro politica di Overflow
+        pub overflowed: usize,
+        // ricevitori pieni con Overflow::Block che non si sono liberati in tempo (try_send/send_timeout)
+        pub full: usize,
+        // id dei ricevitori distrutti o scollegati, tolti dal canale con questo invio
+        pub dropped: Vec<u64>,
+    }
 
-    pub struct MultiChannel {
-        senders: Arc<Mutex<Vec<Sender<u8>>>>,
+    #[derive(Debug, PartialEq)]
+    pub enum SendTimeoutError<T> {
+        // nessun ricevitore si è liberato entro il timeout
+        Timeout(T),
+        Disconnected(T),
     }
 
-    impl MultiChannel {
+    impl<T: Clone + Send> Default for MultiChannel<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    // i ricevitori leggono i valori rimasti e poi ricevono un errore, come con mpsc
+    impl<T: Clone + Send> Drop for MultiChannel<T> {
+        fn drop(&mut self) {
+            for q in self.inner.lock().unwrap_or_else(|e| e.into_inner()).senders.iter() {
+                q.close();
+            }
+        }
+    }
+
+    impl<T: Clone + Send> MultiChannel<T> {
         pub fn new() -> Self {
             Self {
-                senders: Arc::new(Mutex::new(Vec::new())),
+                inner: Arc::new(Mutex::new(Inner { senders: Vec::new(), next_seq: 0, replay: VecDeque::new(), replay_capacity: 0, in_flight: Vec::new() })),
+                next_id: Mutex::new(0),
+                send_lock: Mutex::new(()),
+                on_metrics: None,
+            }
+        }
+
+        // conserva gli ultimi `capacity` valori per subscribe_from
+        pub fn with_replay(self, capacity: usize) -> Self {
+            self.inner.lock().unwrap().replay_capacity = capacity;
+            self
+        }
+
+        // numero di sequenza che riceverà il prossimo valore inviato
+        pub fn next_seq(&self) -> u64 {
+            self.inner.lock().unwrap().next_seq
+        }
+
+        // `hook` riceve le metriche di ogni ricevitore dopo ogni send
+        pub fn on_metrics<C>(mut self, hook: C) -> Self where C: Fn(&Metrics) + Send + Sync + 'static {
+            self.on_metrics = Some(Arc::new(hook));
+            self
+        }
+
+        pub fn metrics(&self) -> Vec<Metrics> {
+            self.inner.lock().unwrap().senders.iter().map(|q| q.metrics()).collect()
+        }
+
+        pub fn subscribe(&self) -> Receiver<T> {
+            self.register(None, Overflow::Block, None)
+        }
+
+        // ricevitore con al massimo `capacity` valori non letti; oltre si applica `overflow`
+        pub fn subscribe_bounded(&self, capacity: usize, overflow: Overflow) -> Receiver<T> {
+            self.register(Some(capacity), overflow, None)
+        }
+
+        // riceve prima i valori con sequenza >= `seq` ancora nel buffer di replay (vedi with_replay),
+        // poi tutti i successivi, senza buchi né duplicati
+        pub fn subscribe_from(&self, seq: u64) -> Receiver<T> {
+            self.register(None, Overflow::Block, Some(seq))
+        }
+
+        fn register(&self, capacity: Option<usize>, overflow: Overflow, from: Option<u64>) -> Receiver<T> {
+            let mut id = self.next_id.lock().unwrap();
+            let q = Arc::new(Queue::new(*id, capacity, overflow));
+            *id += 1;
+            let mut inner = self.inner.lock().unwrap();
+            // sotto lo stesso lock di send: un valore o è già nel buffer, o è in corso di invio
+            // e arriverà alla nuova coda quando l'invio termina, o arriverà come valore nuovo
+            if let Some(from) = from {
+                for (seq, data) in inner.replay.iter().filter(|(seq, _)| *seq >= from) {
+                    q.push((*seq, data.clone()));
+                }
+                for (_, late) in inner.in_flight.iter_mut().filter(|(seq, _)| *seq >= from) {
+                    late.push(Arc::clone(&q));
+                }
             }
+            inner.senders.push(Arc::clone(&q));
+            Receiver { queue: q }
         }
 
-        pub fn subscribe(&self) -> Receiver<u8> {
-            let (tx, rx) = mpsc::channel::<u8>();
-            let mut send = self.senders.lock().unwrap();
-            send.push(tx);
-            rx
+        // errore solo se non c'è nessun ricevitore; il valore entra nel buffer di replay
+        // solo se almeno un ricevitore lo ha accettato
+        pub fn send(&self, data: T) -> Result<Delivery, SendError<T>> {
+            self.send_until(data, None).map_err(|e| SendError(e.into_inner()))
         }
 
-        pub fn send(&self, data: u8) -> Result<(), SendError<u8>> {
-            let mut send = self.senders.lock().unwrap();
+        // non si blocca sui ricevitori pieni con Overflow::Block: li salta e li conta in `full`;
+        // Full se nessun ricevitore ha potuto ricevere il valore
+        pub fn try_send(&self, data: T) -> Result<Delivery, TrySendError<T>> {
+            match self.send_until(data, Some(Instant::now())) {
+                Ok(d) => Ok(d),
+                Err(SendTimeoutError::Timeout(data)) => Err(TrySendError::Full(data)),
+                Err(SendTimeoutError::Disconnected(data)) => Err(TrySendError::Disconnected(data)),
+            }
+        }
+
+        // come send, ma attende i ricevitori pieni con Overflow::Block al massimo per `timeout` in tutto
+        pub fn send_timeout(&self, data: T, timeout: Duration) -> Result<Delivery, SendTimeoutError<T>> {
+            self.send_until(data, Some(Instant::now() + timeout))
+        }
+
+        fn send_until(&self, data: T, deadline: Option<Instant>) -> Result<Delivery, SendTimeoutError<T>> {
+            // gli invii sono serializzati, ma senza il lock di inner: un ricevitore con Overflow::Block
+            // rallenta gli altri invii, non subscribe
+            let _send = self.send_lock.lock().unwrap();
+            let (seq, targets) = {
+                let mut inner = self.inner.lock().unwrap();
+                let seq = inner.next_seq;
+                inner.next_seq += 1;
+                if inner.replay_capacity > 0 && !inner.senders.is_empty() {
+                    inner.in_flight.push((seq, Vec::new()));
+                }
+                (seq, inner.senders.clone())
+            };
+
+            if targets.is_empty() {
+                return Err(SendTimeoutError::Disconnected(data));
+            }
+
+            let mut report = Delivery { seq, ..Delivery::default() };
+            for q in targets {
+                match q.push_until((seq, data.clone()), deadline) {
+                    Ok(Push::Delivered) => report.delivered += 1,
+                    Ok(Push::Dropped) => report.overflowed += 1,
+                    Ok(Push::Gone) => report.dropped.push(q.id()),
+                    Err(_) => report.full += 1,
+                }
+                if let Some(hook) = &self.on_metrics {
+                    hook(&q.metrics());
+                }
+            }
 
-            if send.len() == 0 {
-                return Err(SendError(data));
+            // un valore restituito al chiamante non deve restare nel buffer: chi riprova
+            // creerebbe un duplicato con una nuova sequenza
+            let accepted = report.delivered + report.overflowed > 0;
+            let mut inner = self.inner.lock().unwrap();
+            if !report.dropped.is_empty() {
+                inner.senders.retain(|q| !report.dropped.contains(&q.id()));
+            }
+            if let Some(i) = inner.in_flight.iter().position(|(s, _)| *s == seq) {
+                let (_, late) = inner.in_flight.swap_remove(i);
+                if accepted {
+                    for q in late {
+                        q.push((seq, data.clone()));
+                    }
+                    if inner.replay.len() == inner.replay_capacity {
+                        inner.replay.pop_front();
+                    }
+                    inner.replay.push_back((seq, data.clone()));
+                }
             }
+            drop(inner);
 
-            send.retain(|s| s.send(data).is_ok());
-            Ok(())
-                        
+            // tutti i ricevitori erano già stati distrutti: come se il canale fosse vuoto
+            if report.delivered + report.overflowed + report.full == 0 {
+                return Err(SendTimeoutError::Disconnected(data));
+            }
+            if !accepted {
+                return Err(SendTimeoutError::Timeout(data));
+            }
+            Ok(report)
+        }
+    }
+
+    impl<T> SendTimeoutError<T> {
+        pub fn into_inner(self) -> T {
+            match self {
+                SendTimeoutError::Timeout(data) | SendTimeoutError::Disconnected(data) => data,
+            }
         }
     }
-}
 
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            self.queue.drop_receiver();
+        }
+    }
+
+    impl<T> Receiver<T> {
+        pub fn id(&self) -> u64 {
+            self.queue.id()
+        }
+
+        // bloccante: errore quando il canale è stato distrutto (o il ricevitore scollegato) e non ci sono altri valori
+        pub fn recv(&self) -> Result<T, RecvError> {
+            self.queue.pop().map(|(_, data)| data).ok_or(RecvError)
+        }
+
+        // come recv, con il numero di sequenza del valore
+        pub fn recv_with_seq(&self) -> Result<(u64, T), RecvError> {
+            self.queue.pop().ok_or(RecvError)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+            std::iter::from_fn(|| self.recv().ok())
+        }
+    }
+
+    pub struct IntoIter<T>(Receiver<T>);
+
+    impl<T> Iterator for IntoIter<T> {
+        type Item = T;
+
+        fn next(&mut self) -> Option<T> {
+            self.0.recv().ok()
+        }
+    }
+
+    impl<T> IntoIterator for Receiver<T> {
+        type Item = T;
+        type IntoIter = IntoIter<T>;
+
+        fn into_iter(self) -> IntoIter<T> {
+            IntoIter(self)
+        }
+    }
+}
 
 use std::thread;
 use std::time::Duration;
@@ -68,3 +482,172 @@ fn main() {
     thread::sleep(Duration::from_secs(1));
 }
 
+#[cfg(test)]
+mod tests {
+    use super::bounded::Overflow;
+    use super::channel::{Delivery, MultiChannel, SendTimeoutError};
+    use std::sync::mpsc::{SendError, TrySendError};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_every_receiver_gets_every_value() {
+        let ch = MultiChannel::new();
+        let a = ch.subscribe();
+        let b = ch.subscribe();
+        for i in 0..3 {
+            ch.send(i).unwrap();
+        }
+        drop(ch);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(b.into_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_overflow_policies_and_metrics() {
+        let drops = Arc::new(Mutex::new(0));
+        let d = Arc::clone(&drops);
+        let ch = MultiChannel::new().on_metrics(move |m| {
+            let mut d = d.lock().unwrap();
+            *d = m.dropped.max(*d);
+        });
+        let newest = ch.subscribe_bounded(2, Overflow::DropNewest);
+        let oldest = ch.subscribe_bounded(2, Overflow::DropOldest);
+        let slow = ch.subscribe_bounded(1, Overflow::Disconnect);
+
+        for i in 1..=5 {
+            ch.send(i).unwrap();
+        }
+        assert_eq!(ch.metrics().len(), 2);
+        assert_eq!(*drops.lock().unwrap(), 3);
+        drop(ch);
+        assert_eq!(newest.iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(oldest.iter().collect::<Vec<_>>(), vec![4, 5]);
+        assert_eq!(slow.iter().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_dropped_receivers_are_removed() {
+        let ch = MultiChannel::new();
+        assert!(ch.send(1).is_err());
+        let rx = ch.subscribe();
+        drop(rx);
+        // il ricevitore distrutto non è ancora stato tolto, ma l'esito non cambia
+        assert_eq!(ch.send(2), Err(SendError(2)));
+        assert_eq!(ch.try_send(3), Err(TrySendError::Disconnected(3)));
+    }
+
+    #[test]
+    fn test_subscribe_from_replays_missed_values() {
+        let ch = MultiChannel::new().with_replay(3);
+        // nessun ricevitore: il valore torna al chiamante e non entra nel buffer
+        assert!(ch.send(0).is_err());
+        let early = ch.subscribe();
+        assert!(ch.send(0).is_ok());
+        for i in 1..=3 {
+            ch.send(i).unwrap();
+        }
+        let all = ch.subscribe_from(0);
+        let last = ch.subscribe_from(ch.next_seq() - 1);
+        ch.send(4).unwrap();
+        drop(ch);
+
+        assert_eq!(early.iter().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        // la sequenza 0 è andata all'invio fallito
+        assert_eq!(std::iter::from_fn(|| all.recv_with_seq().ok()).collect::<Vec<_>>(), vec![(2, 1), (3, 2), (4, 3), (5, 4)]);
+        assert_eq!(last.iter().collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_generic_payload_and_delivery_report() {
+        let ch = MultiChannel::new();
+        let a = ch.subscribe();
+        let b = ch.subscribe_bounded(1, Overflow::DropNewest);
+        let c = ch.subscribe();
+        let c_id = c.id();
+        drop(c);
+
+        let report = ch.send("uno".to_string()).unwrap();
+        assert_eq!(report, Delivery { seq: 0, delivered: 2, overflowed: 0, full: 0, dropped: vec![c_id] });
+        let report = ch.send("due".to_string()).unwrap();
+        assert_eq!((report.delivered, report.overflowed, report.dropped.len()), (1, 1, 0));
+        drop(ch);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec!["uno", "due"]);
+        assert_eq!(b.iter().collect::<Vec<_>>(), vec!["uno"]);
+    }
+
+    #[test]
+    fn test_try_send_and_send_timeout() {
+        let ch = Arc::new(MultiChannel::new());
+        assert_eq!(ch.try_send(0), Err(TrySendError::Disconnected(0)));
+        let rx = ch.subscribe_bounded(1, Overflow::Block);
+        assert_eq!(ch.try_send(1).unwrap().delivered, 1);
+        assert_eq!(ch.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(ch.send_timeout(3, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(3)));
+
+        // un ricevitore che legge in tempo sblocca send_timeout
+        let ch2 = Arc::clone(&ch);
+        let sender = thread::spawn(move || ch2.send_timeout(4, Duration::from_secs(5)));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(sender.join().unwrap().unwrap().delivered, 1);
+        assert_eq!(rx.recv(), Ok(4));
+        assert_eq!(ch.metrics()[0].dropped, 2);
+    }
+
+    #[test]
+    fn test_failed_send_is_not_replayed() {
+        let ch = MultiChannel::new().with_replay(10);
+        let rx = ch.subscribe_bounded(1, Overflow::Block);
+        ch.send(1).unwrap();
+        assert_eq!(ch.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(ch.send_timeout(3, Duration::from_millis(10)), Err(SendTimeoutError::Timeout(3)));
+        assert_eq!(rx.recv(), Ok(1));
+        ch.send(4).unwrap();
+
+        let late = ch.subscribe_from(0);
+        drop(ch);
+        assert_eq!(late.iter().collect::<Vec<_>>(), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_subscribe_from_during_blocked_send() {
+        let ch = Arc::new(MultiChannel::new().with_replay(10));
+        let rx = ch.subscribe_bounded(1, Overflow::Block);
+        ch.send(1).unwrap();
+        let c2 = Arc::clone(&ch);
+        let sender = thread::spawn(move || c2.send(2).unwrap());
+        thread::sleep(Duration::from_millis(20));
+
+        // l'invio di 2 è in corso: la nuova coda lo riceve appena viene accettato
+        let late = ch.subscribe_from(0);
+        assert_eq!(rx.recv(), Ok(1));
+        sender.join().unwrap();
+        assert_eq!(rx.recv(), Ok(2));
+        ch.send(3).unwrap();
+        drop(ch);
+        assert_eq!(std::iter::from_fn(|| late.recv_with_seq().ok()).collect::<Vec<_>>(), vec![(0, 1), (1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn test_concurrent_sends_arrive_in_seq_order() {
+        let ch = Arc::new(MultiChannel::new().with_replay(1000));
+        let early = ch.subscribe();
+        let senders: Vec<_> = (0..4).map(|_| {
+            let ch = Arc::clone(&ch);
+            thread::spawn(move || for i in 0..200 { ch.send(i).unwrap(); })
+        }).collect();
+        let late = ch.subscribe_from(0);
+        for s in senders {
+            s.join().unwrap();
+        }
+        drop(ch);
+
+        // nessun ricevitore vede una sequenza prima di una precedente
+        for rx in [early, late] {
+            let seqs: Vec<_> = std::iter::from_fn(|| rx.recv_with_seq().ok()).map(|(seq, _)| seq).collect();
+            assert_eq!(seqs, (0..800).collect::<Vec<_>>());
+        }
+    }
+}
@@ -1,57 +1,284 @@
+pub mod topic {
+    use std::collections::HashMap;
+    use std::fmt;
+
+    // pattern di sottoscrizione: segmenti separati da '.', dove `*` corrisponde a esattamente
+    // un segmento e `#`, solo come ultimo segmento, a zero o più segmenti finali.
+    //   "sensors.kitchen.temp"  esatto
+    //   "sensors.*.temp"        wildcard
+    //   "sensors.#"             prefisso (anche "sensors" stesso)
+    #[derive(Debug, PartialEq)]
+    pub enum PatternError {
+        EmptySegment,
+        // `#` usato in una posizione diversa dall'ultima
+        MisplacedHash,
+    }
+
+    impl fmt::Display for PatternError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                PatternError::EmptySegment => write!(f, "topic pattern contains an empty segment"),
+                PatternError::MisplacedHash => write!(f, "'#' is only allowed as the last segment"),
+            }
+        }
+    }
+
+    impl std::error::Error for PatternError {}
+
+    pub fn validate(pattern: &str) -> Result<(), PatternError> {
+        let segments: Vec<&str> = pattern.split('.').collect();
+        for (i, s) in segments.iter().enumerate() {
+            if s.is_empty() {
+                return Err(PatternError::EmptySegment);
+            }
+            if *s == "#" && i + 1 != segments.len() {
+                return Err(PatternError::MisplacedHash);
+            }
+        }
+        Ok(())
+    }
+
+    struct Node {
+        children: HashMap<String, Node>,
+        star: Option<Box<Node>>,
+        // sottoscrizioni che terminano qui
+        exact: Vec<u64>,
+        // sottoscrizioni con `#` dopo questo nodo
+        rest: Vec<u64>,
+    }
+
+    impl Node {
+        fn new() -> Self {
+            Node { children: HashMap::new(), star: None, exact: Vec::new(), rest: Vec::new() }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.children.is_empty() && self.star.is_none() && self.exact.is_empty() && self.rest.is_empty()
+        }
+    }
+
+    // il costo di una ricerca dipende dalla profondità del topic e dai wildcard attraversati,
+    // non dal numero di sottoscrizioni
+    pub struct TopicTrie {
+        root: Node,
+    }
+
+    impl Default for TopicTrie {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl TopicTrie {
+        pub fn new() -> Self {
+            TopicTrie { root: Node::new() }
+        }
+
+        pub fn insert(&mut self, pattern: &str, id: u64) -> Result<(), PatternError> {
+            validate(pattern)?;
+            let mut node = &mut self.root;
+            for s in pattern.split('.') {
+                node = match s {
+                    "#" => {
+                        node.rest.push(id);
+                        return Ok(());
+                    },
+                    "*" => node.star.get_or_insert_with(|| Box::new(Node::new())),
+                    s => node.children.entry(s.to_string()).or_insert_with(Node::new),
+                };
+            }
+            node.exact.push(id);
+            Ok(())
+        }
+
+        // toglie `id` registrato con `pattern` e pota i nodi rimasti vuoti
+        pub fn remove(&mut self, pattern: &str, id: u64) -> bool {
+            fn remove_in(node: &mut Node, segments: &[&str], id: u64) -> bool {
+                let list = match segments {
+                    [] => &mut node.exact,
+                    ["#"] => &mut node.rest,
+                    [s, tail @ ..] => {
+                        let removed = match *s {
+                            "*" => node.star.as_deref_mut().is_some_and(|n| remove_in(n, tail, id)),
+                            s => node.children.get_mut(s).is_some_and(|n| remove_in(n, tail, id)),
+                        };
+                        if node.star.as_ref().is_some_and(|n| n.is_empty()) {
+                            node.star = None;
+                        }
+                        if node.children.get(*s).is_some_and(|n| n.is_empty()) {
+                            node.children.remove(*s);
+                        }
+                        return removed;
+                    },
+                };
+                match list.iter().position(|&x| x == id) {
+                    Some(i) => {
+                        list.swap_remove(i);
+                        true
+                    },
+                    None => false,
+                }
+            }
+            let segments: Vec<&str> = pattern.split('.').collect();
+            remove_in(&mut self.root, &segments, id)
+        }
+
+        // sottoscrizioni il cui pattern corrisponde a `topic`
+        pub fn matches(&self, topic: &str) -> Vec<u64> {
+            fn walk(node: &Node, segments: &[&str], out: &mut Vec<u64>) {
+                out.extend_from_slice(&node.rest);
+                match segments {
+                    [] => out.extend_from_slice(&node.exact),
+                    [s, tail @ ..] => {
+                        if let Some(child) = node.children.get(*s) {
+                            walk(child, tail, out);
+                        }
+                        if let Some(star) = &node.star {
+                            walk(star, tail, out);
+                        }
+                    },
+                }
+            }
+            let mut out = Vec::new();
+            let segments: Vec<&str> = topic.split('.').collect();
+            walk(&self.root, &segments, &mut out);
+            out
+        }
+    }
+
+}
+
 
 pub mod dispatcher {
+    use std::collections::HashMap;
     use std::sync::mpsc::{self, Receiver, Sender};
-    use std::sync::{Arc, Mutex};
+    use std::sync::{Arc, Mutex, Weak};
+    use crate::topic::{PatternError, TopicTrie};
+
+    struct Inner<Msg> {
+        next_id: u64,
+        senders: HashMap<u64, (String, Sender<Msg>)>,
+        topics: TopicTrie,
+        closed: bool,
+    }
+
+    impl<Msg> Inner<Msg> {
+        fn remove(&mut self, id: u64) -> bool {
+            match self.senders.remove(&id) {
+                Some((pattern, _)) => {
+                    self.topics.remove(&pattern, id);
+                    true
+                },
+                None => false,
+            }
+        }
+
+        // invia a tutti gli id indicati e toglie le sottoscrizioni il cui receiver non esiste più
+        fn send_to(&mut self, ids: Vec<u64>, msg: &Msg) where Msg: Clone {
+            let gone: Vec<u64> = ids.into_iter()
+                .filter(|id| self.senders.get(id).is_some_and(|(_, s)| s.send(msg.clone()).is_err()))
+                .collect();
+            for id in gone {
+                self.remove(id);
+            }
+        }
+    }
 
     pub struct Dispatcher<Msg: Clone>{
-        senders: Arc<Mutex<Vec<Sender<Msg>>>>,
+        senders: Arc<Mutex<Inner<Msg>>>,
     }
 
     pub struct Subscription<Msg: Clone>{
         receiver: Receiver<Msg>,
+        id: u64,
+        // non tiene in vita il dispatcher
+        dispatcher: Weak<Mutex<Inner<Msg>>>,
+    }
+
+    impl<Msg: Clone> Default for Dispatcher<Msg> {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl<Msg: Clone> Dispatcher<Msg> {
         pub fn new() -> Self {
             Self {
-                senders: Arc::new(Mutex::new(Vec::new())),
+                senders: Arc::new(Mutex::new(Inner { next_id: 0, senders: HashMap::new(), topics: TopicTrie::new(), closed: false })),
             }
         }
 
+        // broadcast: il messaggio arriva a tutte le sottoscrizioni, qualunque sia il loro pattern
         pub fn dispatch(&self, msg: Msg){
             let mut sends = self.senders.lock().unwrap();
+            let ids = sends.senders.keys().copied().collect();
+            sends.send_to(ids, &msg);
+        }
 
-            for i in (0..sends.len()){
-                match sends[i].send(msg.clone()) {
-                    Ok(_) => {()},
-                    Err(_) => {sends.remove(i);},
-                }
-            }
-
+        // il messaggio arriva solo alle sottoscrizioni il cui pattern corrisponde a `topic`
+        pub fn dispatch_to(&self, topic: &str, msg: Msg) {
+            let mut sends = self.senders.lock().unwrap();
+            let ids = sends.topics.matches(topic);
+            sends.send_to(ids, &msg);
         }
 
+        // riceve tutti i messaggi, equivale a subscribe_topic("#")
         pub fn subscribe(&self) -> Subscription<Msg> {
-            let (tx, rx) = mpsc::channel();
+            self.subscribe_topic("#").unwrap()
+        }
+
+        // vedi topic per la sintassi dei pattern
+        pub fn subscribe_topic(&self, pattern: &str) -> Result<Subscription<Msg>, PatternError> {
             let mut send = self.senders.lock().unwrap();
-            send.push(tx);
-            Subscription { receiver: rx, }
+            let id = send.next_id;
+            let (tx, rx) = mpsc::channel();
+            let dispatcher = Arc::downgrade(&self.senders);
+            // dopo la close il sender viene scartato subito e read() restituisce None
+            if send.closed {
+                crate::topic::validate(pattern)?;
+                return Ok(Subscription { receiver: rx, id, dispatcher });
+            }
+            send.topics.insert(pattern, id)?;
+            send.next_id += 1;
+            send.senders.insert(id, (pattern.to_string(), tx));
+            Ok(Subscription { receiver: rx, id, dispatcher })
+        }
+
+        pub fn subscriber_count(&self) -> usize {
+            self.senders.lock().unwrap().senders.len()
+        }
+
+        // chiude tutti i canali: i subscriber leggono i messaggi rimasti e poi ricevono None
+        pub fn close(&self) {
+            let mut send = self.senders.lock().unwrap_or_else(|e| e.into_inner());
+            send.closed = true;
+            send.senders.clear();
+            send.topics = TopicTrie::new();
         }
 
     }
 
     impl<Msg:Clone> Subscription<Msg>{
         pub fn read(&self) -> Option<Msg>{
-            match self.receiver.recv() {
-                Ok(msg) => Some(msg),
-                Err(_) => None,
-            }
+            self.receiver.recv().ok()
         }
 
+        // equivale al drop della subscription
+        pub fn unsubscribe(self) {}
+
+    }
+
+    impl<Msg: Clone> Drop for Subscription<Msg> {
+        fn drop(&mut self) {
+            if let Some(inner) = self.dispatcher.upgrade() {
+                inner.lock().unwrap_or_else(|e| e.into_inner()).remove(self.id);
+            }
+        }
     }
 }
 
 use std::thread;
-use crate::dispatcher::{Dispatcher, Subscription};
+use crate::dispatcher::Dispatcher;
 use std::time::Duration;
 
 fn main() {
@@ -95,3 +322,71 @@ fn main() {
     handle2.join().unwrap();
 }
 
+#[cfg(test)]
+mod tests {
+    use super::dispatcher::{Dispatcher, Subscription};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn read_all(s: Subscription<&'static str>) -> Vec<&'static str> {
+        std::iter::from_fn(move || s.read()).collect()
+    }
+
+    #[test]
+    fn test_topic_routing() {
+        let d = Dispatcher::new();
+        let temps = d.subscribe_topic("sensors.*.temp").unwrap();
+        let kitchen = d.subscribe_topic("sensors.kitchen.#").unwrap();
+        let all = d.subscribe();
+
+        d.dispatch_to("sensors.kitchen.temp", "k-temp");
+        d.dispatch_to("sensors.kitchen.light.on", "k-light");
+        d.dispatch_to("sensors.garage.temp", "g-temp");
+        d.dispatch("broadcast");
+        drop(d);
+
+        assert_eq!(read_all(temps), vec!["k-temp", "g-temp", "broadcast"]);
+        assert_eq!(read_all(kitchen), vec!["k-temp", "k-light", "broadcast"]);
+        assert_eq!(read_all(all), vec!["k-temp", "k-light", "g-temp", "broadcast"]);
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let d = Dispatcher::<u8>::new();
+        assert!(d.subscribe_topic("#.a").is_err());
+        assert!(d.subscribe_topic("").is_err());
+    }
+
+    #[test]
+    fn test_subscription_lifecycle() {
+        let d = Dispatcher::new();
+        let a = d.subscribe();
+        let b = d.subscribe_topic("x").unwrap();
+        drop(a);
+        assert_eq!(d.subscriber_count(), 1);
+        d.dispatch("one");
+        d.close();
+        d.dispatch("two");
+        assert_eq!(d.subscriber_count(), 0);
+        assert_eq!(read_all(b), vec!["one"]);
+        assert_eq!(d.subscribe().read(), None);
+    }
+
+    #[test]
+    fn test_concurrent_subscribe_dispatch_drop() {
+        let d = Arc::new(Dispatcher::new());
+        let handles: Vec<_> = (0..8).map(|i| {
+            let d = Arc::clone(&d);
+            thread::spawn(move || for _ in 0..200 {
+                let s = d.subscribe();
+                d.dispatch("x");
+                assert_eq!(s.read(), Some("x"));
+                if i % 2 == 0 { s.unsubscribe() }
+            })
+        }).collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(d.subscriber_count(), 0);
+    }
+}
@@ -14,6 +14,7 @@ pub mod cache {
     }
 
     impl<K: Clone + Eq + Hash + Copy, V: Copy> Cache<K, V> {
+        #[allow(clippy::new_without_default)]
         pub fn new() -> Self {
             Self {
                 map: Arc::new(Mutex::new(HashMap::new())),
@@ -45,7 +46,149 @@ pub mod cache {
     }
 }
 
+pub mod sharded {
+    use std::{collections::{HashMap, hash_map::RandomState}, rc::Rc, sync::{Arc, Condvar, Mutex}};
+    use std::hash::{BuildHasher, Hash};
+
+    // ogni chiave in calcolo ha la sua condvar: si svegliano solo i thread che aspettano quella chiave
+    struct Slot<V> {
+        value: Mutex<SlotState<V>>,
+        condvar: Condvar,
+    }
+
+    enum SlotState<V> {
+        Pending,
+        Ready(V),
+        // il calcolo è andato in panic
+        Abandoned,
+    }
+
+    #[derive(Clone)]
+    enum Entry<V: Copy> {
+        Computing(Arc<Slot<V>>),
+        Ready(V),
+    }
+
+    type Shard<K, V> = Mutex<HashMap<K, Entry<V>>>;
+
+    pub struct ShardedCache<K: Clone + Eq + Hash + Copy, V: Copy> {
+        shards: Vec<Shard<K, V>>,
+        hasher: RandomState,
+    }
+
+    impl<K: Clone + Eq + Hash + Copy, V: Copy> ShardedCache<K, V> {
+        pub fn new(n: usize) -> Self {
+            assert!(n > 0, "n must be greater than zero");
+            Self {
+                shards: (0..n).map(|_| Mutex::new(HashMap::new())).collect(),
+                hasher: RandomState::new(),
+            }
+        }
+
+        pub fn get<F>(&self, f: F, key: K) -> Rc<V> where F: Fn(K) -> V + Send + Sync + 'static, {
+            let shard = &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()];
+            loop {
+                let mut map = shard.lock().unwrap();
+
+                let slot = match map.get(&key).cloned() {
+                    None => {
+                        let slot = Arc::new(Slot { value: Mutex::new(SlotState::Pending), condvar: Condvar::new() });
+                        map.insert(key, Entry::Computing(Arc::clone(&slot)));
+                        drop(map);
+
+                        let guard = SlotGuard { shard, key, slot: &slot, armed: true };
+                        let value = f(key);
+                        guard.disarm();
+                        shard.lock().unwrap().insert(key, Entry::Ready(value));
+                        *slot.value.lock().unwrap() = SlotState::Ready(value);
+                        slot.condvar.notify_all();
+                        return Rc::new(value);
+                    },
+                    Some(Entry::Ready(val)) => return Rc::new(val),
+                    Some(Entry::Computing(slot)) => slot,
+                };
+                drop(map);
+                let value = slot.value.lock().unwrap();
+                let value = slot.condvar.wait_while(value, |v| matches!(v, SlotState::Pending)).unwrap();
+                if let SlotState::Ready(val) = *value {
+                    return Rc::new(val);
+                }
+                // il calcolo è andato in panic: si riprova
+            }
+        }
+    }
+
+    // se f va in panic libera la chiave e sveglia chi attende su quello slot
+    struct SlotGuard<'a, K: Eq + Hash + Copy, V: Copy> {
+        shard: &'a Shard<K, V>,
+        key: K,
+        slot: &'a Arc<Slot<V>>,
+        armed: bool,
+    }
+
+    impl<K: Eq + Hash + Copy, V: Copy> SlotGuard<'_, K, V> {
+        fn disarm(mut self) {
+            self.armed = false;
+        }
+    }
+
+    impl<K: Eq + Hash + Copy, V: Copy> Drop for SlotGuard<'_, K, V> {
+        fn drop(&mut self) {
+            if !self.armed {
+                return;
+            }
+            let mut map = self.shard.lock().unwrap_or_else(|e| e.into_inner());
+            if matches!(map.get(&self.key), Some(Entry::Computing(s)) if Arc::ptr_eq(s, self.slot)) {
+                map.remove(&self.key);
+            }
+            drop(map);
+            *self.slot.value.lock().unwrap_or_else(|e| e.into_inner()) = SlotState::Abandoned;
+            self.slot.condvar.notify_all();
+        }
+    }
+}
+
+// `cargo run --release -- bench`: stesso carico sulla cache con un solo lock e su quella a segmenti
+fn bench() {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn work(k: u32) -> u32 {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_micros(20) {
+            std::hint::spin_loop();
+        }
+        k * 10
+    }
+
+    fn run<G>(get: G) -> Duration where G: Fn(u32) -> u32 + Send + Sync + 'static {
+        let get = Arc::new(get);
+        let start = Instant::now();
+        let handles: Vec<_> = (0..8u32).map(|t| {
+            let get = Arc::clone(&get);
+            thread::spawn(move || (0..50_000u32).map(|i| get((t * 7919 + i * 31) % 5_000)).fold(0u32, u32::wrapping_add))
+        }).collect();
+        for h in handles {
+            std::hint::black_box(h.join().unwrap());
+        }
+        start.elapsed()
+    }
+
+    let single = Arc::new(cache::Cache::<u32, u32>::new());
+    println!("single lock : {:?}", run(move |k| *single.get(work, k)));
+    for n in [4, 16, 64] {
+        let sharded = Arc::new(sharded::ShardedCache::<u32, u32>::new(n));
+        println!("sharded x{:<3}: {:?}", n, run(move |k| *sharded.get(work, k)));
+    }
+}
+
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("bench") {
+        bench();
+        return;
+    }
+
     let cache = cache::Cache::<u32, u32>::new();
 
     // Funzione di calcolo semplice
@@ -67,6 +210,62 @@ fn main() {
     println!("Valore per chiave 2: {}", *val3);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::sharded::ShardedCache;
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_sharded_computes_each_key_once() {
+        let cache = Arc::new(ShardedCache::<u32, u32>::new(4));
+        let handles: Vec<_> = (0..12u32).map(|i| {
+            let c = Arc::clone(&cache);
+            thread::spawn(move || *c.get(|k| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(50));
+                k * 10
+            }, i % 3))
+        }).collect();
 
+        for (i, h) in handles.into_iter().enumerate() {
+            assert_eq!(h.join().unwrap(), (i as u32 % 3) * 10);
+        }
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_sharded_other_keys_are_not_blocked() {
+        let cache = Arc::new(ShardedCache::<u32, u32>::new(1));
+        let c = Arc::clone(&cache);
+        let slow = thread::spawn(move || *c.get(|k| { thread::sleep(Duration::from_millis(300)); k }, 1));
+
+        thread::sleep(Duration::from_millis(30));
+        let start = Instant::now();
+        assert_eq!(*cache.get(|k| k, 2), 2);
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(slow.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_sharded_panic_wakes_waiters_of_the_same_key() {
+        let cache = Arc::new(ShardedCache::<u32, u32>::new(4));
+        let c = Arc::clone(&cache);
+        let panicking = thread::spawn(move || {
+            c.get(|_| {
+                thread::sleep(Duration::from_millis(100));
+                panic!("compute failed");
+            }, 1);
+        });
 
+        thread::sleep(Duration::from_millis(30));
+        let c = Arc::clone(&cache);
+        let waiter = thread::spawn(move || *c.get(|k| k + 1, 1));
 
+        assert!(panicking.join().is_err());
+        assert_eq!(waiter.join().unwrap(), 2);
+    }
+}
@@ -1,80 +1,183 @@
 
 pub mod executor {
-    use std::{sync::{Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::{self, Duration, Instant}};
+    use std::{panic::{self, AssertUnwindSafe}, sync::{Arc, Condvar, Mutex, mpsc}, thread::{self, JoinHandle}, time::{Duration, Instant}};
 
-    #[derive(PartialEq, Copy, Clone)]
+    // gli stati sono ordinati: una chiusura può solo passare a uno stato successivo
+    #[derive(PartialEq, PartialOrd, Copy, Clone)]
     pub enum State{
         Open,
+        Drain,
+        DrainNow,
         Close,
     }
-    pub struct DelayedExecutor <F: FnOnce () -> () + Send + 'static + Clone> {
-        tasks: Arc<Mutex<(Vec<(F, Instant)>, State)>>,
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum CloseMode {
+        // i task in coda partono alla loro scadenza, poi l'executor si ferma
+        Drain,
+        // i task in coda vengono eseguiti subito, senza attendere la scadenza
+        DrainNow,
+        // i task in coda vengono tolti e restituiti al chiamante
+        Abort,
+    }
+
+    // task in attesa ordinati per scadenza decrescente (il prossimo è in fondo) e stato dell'executor
+    type Tasks<F> = Arc<Mutex<(Vec<(F, Instant)>, State)>>;
+
+    pub struct DelayedExecutor <F: FnOnce() + Send + 'static> {
+        tasks: Tasks<F>,
         condvar: Arc<Condvar>,
-        worker: Option<JoinHandle<()>>,
+        timer: Option<JoinHandle<()>>,
+        workers: Vec<JoinHandle<()>>,
+        alive: Alive,
     }
 
-    impl<F: FnOnce() -> () + Send + 'static + Clone> Drop for DelayedExecutor<F> {
+    // numero di thread (timer e worker) ancora attivi, usato da await_termination
+    type Alive = Arc<(Mutex<usize>, Condvar)>;
+
+    struct AliveGuard(Alive);
+
+    impl Drop for AliveGuard {
         fn drop(&mut self) {
-            self.close(true);
-            self.worker.take().unwrap().join().unwrap();
+            *self.0.0.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+            self.0.1.notify_all();
+        }
+    }
+
+    impl<F: FnOnce() + Send + 'static> Drop for DelayedExecutor<F> {
+        fn drop(&mut self) {
+            // senza una close esplicita i task in coda vengono scartati; un Drain già richiesto viene invece atteso
+            if self.tasks.lock().unwrap().1 == State::Open {
+                self.close_with(CloseMode::Abort);
+            }
+            // il timer chiude il canale uscendo, i worker terminano dopo il task in corso.
+            // i panic dei task sono già isolati: un errore di join non va propagato dal drop
+            if let Some(timer) = self.timer.take() {
+                let _ = timer.join();
+            }
+            for w in self.workers.drain(..) {
+                let _ = w.join();
+            }
+        }
+    }
+
+    impl<F: FnOnce() + Send + 'static> Default for DelayedExecutor<F> {
+        fn default() -> Self {
+            Self::new()
         }
     }
 
-    impl<F: FnOnce() -> () + Send + 'static + Clone> DelayedExecutor<F> {
+    impl<F: FnOnce() + Send + 'static> DelayedExecutor<F> {
         pub fn new() -> Self {
+            Self::with_workers(1)
+        }
 
-            let mut tasks = Arc::new(Mutex::new((Vec::<(F, Instant)>::new(), State::Open)));
+        // il thread timer passa i task scaduti a un pool di `workers` thread che li eseguono
+        pub fn with_workers(workers: usize) -> Self {
+            assert!(workers > 0, "workers must be greater than zero");
+            let tasks: Tasks<F> = Arc::new(Mutex::new((Vec::new(), State::Open)));
             let condvar = Arc::new(Condvar::new());
+
+            let alive: Alive = Arc::new((Mutex::new(workers + 1), Condvar::new()));
+            let (tx, rx) = mpsc::channel::<F>();
+            let rx = Arc::new(Mutex::new(rx));
+            let workers = (0..workers).map(|_| {
+                let rx = Arc::clone(&rx);
+                let guard = AliveGuard(Arc::clone(&alive));
+                thread::spawn(move || {
+                    let _guard = guard;
+                    loop {
+                        let f = rx.lock().unwrap().recv();
+                        match f {
+                            // un task che va in panic non deve far terminare il worker
+                            Ok(f) => { let _ = panic::catch_unwind(AssertUnwindSafe(f)); },
+                            Err(_) => break,
+                        }
+                    }
+                })
+            }).collect();
+
             let cond_c = Arc::clone(&condvar);
-            let mut task_c = tasks.clone();
-            let jh = thread::spawn(move ||{
+            let task_c = Arc::clone(&tasks);
+            let guard = AliveGuard(Arc::clone(&alive));
+            let timer = thread::spawn(move ||{
+                let _guard = guard;
                 loop {
                     let mut t = task_c.lock().unwrap();
-                    if t.1 == State::Open {
-                        t = cond_c.wait_while(t, |ta| ta.0.len() != 0).unwrap();
-                        let now = Instant::now();
-                        let (f, i) = t.0.last().unwrap();
-                        if *i <= now {
-                            let val = (*t).0.pop().unwrap();
-                            val.0();
-                        }
-                        else {
-                            let d = i.duration_since(Instant::now());
-                            t = cond_c.wait_timeout(t, d).unwrap().0;
+                    t = cond_c.wait_while(t, |ta| ta.0.is_empty() && ta.1 == State::Open).unwrap();
+                    // coda vuota durante un Drain: non arriveranno altri task
+                    if t.1 == State::Close || t.0.is_empty() {
+                        break;
+                    }
+
+                    let now = Instant::now();
+                    let i = t.0.last().unwrap().1;
+                    if i <= now || t.1 == State::DrainNow {
+                        let (f, _) = t.0.pop().unwrap();
+                        drop(t);
+                        if tx.send(f).is_err() {
+                            break;
                         }
                     }
                     else {
-                        break;
+                        drop(cond_c.wait_timeout(t, i - now).unwrap());
                     }
                 }
             });
 
             Self {
-                tasks: tasks,
-                condvar: condvar,
-                worker: Some(jh),
+                tasks,
+                condvar,
+                timer: Some(timer),
+                workers,
+                alive,
             }
         }
 
         pub fn execute(&self, f: F, delay: Duration) -> bool {
             let mut t = self.tasks.lock().unwrap();
-            if t.1 == State::Close {
+            if t.1 != State::Open {
                 return false;
             }
             let i = Instant::now() + delay;
             t.0.push((f, i));
-            t.0.sort_by(|a,b|  b.1.cmp(&a.1));
+            t.0.sort_by_key(|task| std::cmp::Reverse(task.1));
             self.condvar.notify_all();
-            return true;
+            true
         }
 
+        // close(true) equivale a CloseMode::Abort scartando i task, close(false) a CloseMode::Drain
         pub fn close(&self, drop_pending_tasks: bool) {
+            self.close_with(if drop_pending_tasks { CloseMode::Abort } else { CloseMode::Drain });
+        }
+
+        // chiude l'executor: da qui in poi execute restituisce false. Con Abort restituisce
+        // i task tolti dalla coda in ordine di scadenza
+        pub fn close_with(&self, mode: CloseMode) -> Vec<F> {
             let mut t = self.tasks.lock().unwrap();
-            t.1 = State::Close;
-            if drop_pending_tasks == true {
-                t.0.clear();
+            let next = match mode {
+                CloseMode::Drain => State::Drain,
+                CloseMode::DrainNow => State::DrainNow,
+                CloseMode::Abort => State::Close,
+            };
+            if next > t.1 {
+                t.1 = next;
             }
             self.condvar.notify_all();
+
+            if mode == CloseMode::Abort {
+                t.0.drain(..).rev().map(|(f, _)| f).collect()
+            } else {
+                Vec::new()
+            }
+        }
+
+        // attende al massimo `timeout` che l'executor, dopo una close, abbia terminato tutti i task;
+        // restituisce true se tutti i thread sono terminati
+        pub fn await_termination(&self, timeout: Duration) -> bool {
+            let (alive, cv) = &*self.alive;
+            let n = alive.lock().unwrap();
+            *cv.wait_timeout_while(n, timeout, |n| *n > 0).unwrap().0 == 0
         }
     }
 }
@@ -82,3 +185,115 @@ pub mod executor {
 fn main() {
     println!("Hello, world!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::executor::{CloseMode, DelayedExecutor};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    type Task = Box<dyn FnOnce() + Send>;
+
+    #[test]
+    fn test_slow_task_does_not_delay_others() {
+        let exec = DelayedExecutor::<Task>::with_workers(2);
+        let (tx, rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        // con un solo worker il primo task resterebbe bloccato fino al timeout
+        let d = done_tx.clone();
+        exec.execute(Box::new(move || d.send(rx.recv_timeout(Duration::from_secs(2)).is_ok()).unwrap()), Duration::ZERO);
+        exec.execute(Box::new(move || tx.send(()).unwrap()), Duration::from_millis(20));
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(3)), Ok(true));
+    }
+
+    #[test]
+    fn test_panic_does_not_kill_the_executor() {
+        let exec = DelayedExecutor::<Task>::new();
+        let (tx, rx) = mpsc::channel();
+
+        exec.execute(Box::new(|| panic!("boom")), Duration::ZERO);
+        exec.execute(Box::new(move || tx.send(1).unwrap()), Duration::from_millis(10));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+    }
+
+    #[test]
+    fn test_close_drain_runs_pending_at_their_deadline() {
+        let start = Instant::now();
+        let exec = DelayedExecutor::<Task>::new();
+        let (tx, rx) = mpsc::channel();
+        for (id, ms) in [(2, 60), (1, 30)] {
+            let tx = tx.clone();
+            exec.execute(Box::new(move || tx.send((id, start.elapsed())).unwrap()), Duration::from_millis(ms));
+        }
+        drop(tx);
+
+        assert!(exec.close_with(CloseMode::Drain).is_empty());
+        assert!(!exec.execute(Box::new(|| {}), Duration::ZERO));
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        let got: Vec<_> = rx.iter().collect();
+        assert_eq!(got.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(got[1].1 >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_close_drain_now_runs_pending_immediately() {
+        let exec = DelayedExecutor::<Task>::with_workers(2);
+        let (tx, rx) = mpsc::channel();
+        for id in 0..4 {
+            let tx = tx.clone();
+            exec.execute(Box::new(move || tx.send(id).unwrap()), Duration::from_secs(60));
+        }
+        drop(tx);
+
+        exec.close_with(CloseMode::DrainNow);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        let mut got: Vec<_> = rx.iter().collect();
+        got.sort();
+        assert_eq!(got, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_close_abort_returns_pending_tasks() {
+        let exec = DelayedExecutor::<Task>::new();
+        let (tx, rx) = mpsc::channel();
+        for (id, secs) in [(2, 20), (1, 10), (3, 30)] {
+            let tx = tx.clone();
+            exec.execute(Box::new(move || tx.send(id).unwrap()), Duration::from_secs(secs));
+        }
+
+        let pending = exec.close_with(CloseMode::Abort);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        assert!(rx.try_recv().is_err());
+        for f in pending {
+            f();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_close_runs_or_drops_pending() {
+        for (drop_pending, expected) in [(false, vec![1, 2]), (true, vec![])] {
+            let exec = DelayedExecutor::<Task>::new();
+            let (tx, rx) = mpsc::channel();
+            for (id, ms) in [(2, 60), (1, 30)] {
+                let tx = tx.clone();
+                exec.execute(Box::new(move || tx.send(id).unwrap()), Duration::from_millis(ms));
+            }
+            drop(tx);
+            exec.close(drop_pending);
+            assert!(!exec.execute(Box::new(|| {}), Duration::ZERO));
+            drop(exec);
+            assert_eq!(rx.iter().collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_drop_discards_pending_tasks() {
+        let exec = DelayedExecutor::<Task>::new();
+        let (tx, rx) = mpsc::channel();
+        exec.execute(Box::new(move || tx.send(()).unwrap()), Duration::from_secs(60));
+        drop(exec);
+        assert!(rx.recv().is_err());
+    }
+}
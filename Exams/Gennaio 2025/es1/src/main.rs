@@ -46,81 +46,411 @@ Alla distruzione (Drop) della struct, il thread deve essere chiuso come in close
 
  */
 
+pub mod clock {
+    use std::sync::{Arc, Mutex, Weak};
+    use std::time::{Duration, Instant};
+
+    pub type Listener = Arc<dyn Fn() + Send + Sync>;
+
+    // sorgente del tempo per code e timer: nei test si usa MockClock e si fa avanzare il tempo a mano
+    pub trait Clock: Send + Sync {
+        fn now(&self) -> Instant;
+
+        // quanto attendere davvero sulla condvar per arrivare a `deadline`;
+        // None = attendere finché non arriva una notifica
+        fn wait_for(&self, deadline: Instant) -> Option<Duration>;
+
+        // `f` viene chiamata ogni volta che il tempo avanza senza che passi tempo reale;
+        // il clock la tiene con un riferimento debole, quindi resta attiva finché il chiamante tiene `f`
+        fn on_advance(&self, _f: &Listener) {}
+    }
+
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn wait_for(&self, deadline: Instant) -> Option<Duration> {
+            Some(deadline.saturating_duration_since(Instant::now()))
+        }
+    }
+
+    pub struct MockClock {
+        now: Mutex<Instant>,
+        listeners: Mutex<Vec<Weak<dyn Fn() + Send + Sync>>>,
+    }
+
+    impl MockClock {
+        pub fn new() -> Arc<Self> {
+            Arc::new(MockClock { now: Mutex::new(Instant::now()), listeners: Mutex::new(Vec::new()) })
+        }
+
+        pub fn advance(&self, d: Duration) {
+            *self.now.lock().unwrap() += d;
+            // i listener di code ed executor già distrutti vengono tolti
+            let listeners: Vec<Listener> = {
+                let mut l = self.listeners.lock().unwrap();
+                l.retain(|f| f.strong_count() > 0);
+                l.iter().filter_map(Weak::upgrade).collect()
+            };
+            for f in listeners {
+                f();
+            }
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn wait_for(&self, _deadline: Instant) -> Option<Duration> {
+            None
+        }
+
+        fn on_advance(&self, f: &Listener) {
+            self.listeners.lock().unwrap().push(Arc::downgrade(f));
+        }
+    }
+}
+
 pub mod executor {
-    use std::{sync::{Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::{Duration}};
+    use std::{panic::{self, AssertUnwindSafe}, sync::{Arc, Condvar, Mutex, atomic::{AtomicUsize, Ordering}, mpsc}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+    use crate::clock::{Clock, Listener, SystemClock};
 
 
-    #[derive(PartialEq)]
+    // gli stati sono ordinati: una chiusura può solo passare a uno stato successivo
+    #[derive(PartialEq, PartialOrd, Clone, Copy)]
     pub enum State{
         Open,
+        Drain,
+        DrainNow,
+        Close,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum CloseMode {
+        // i task in coda vengono eseguiti allo scadere del batch corrente, poi l'executor si ferma
+        Drain,
+        // i task in coda vengono eseguiti subito
+        DrainNow,
+        // i task in coda vengono tolti e restituiti al chiamante
+        Abort,
+    }
+
+    // cosa ha fatto partire un batch
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Trigger {
+        Interval,
+        // raggiunto max_batch_size
+        Size,
+        // chiamata esplicita a flush()
+        Flush,
+        // ultimo batch dopo una close
         Close,
     }
 
+    // passate alla callback di on_batch quando tutti i task di un batch sono terminati
+    #[derive(Clone, Debug)]
+    pub struct BatchStats {
+        pub trigger: Trigger,
+        pub size: usize,
+        // task del batch andati in panic
+        pub panicked: usize,
+        // attesa in coda del task più vecchio del batch
+        pub queued: Duration,
+        // dalla partenza del batch alla fine del suo ultimo task
+        pub elapsed: Duration,
+    }
+
+    pub type Job = Box<dyn FnOnce() + Send + 'static>;
+    type Callback = Arc<dyn Fn(&BatchStats) + Send + Sync + 'static>;
+
+    struct Inner {
+        // task in attesa con l'istante di submit
+        tasks: Vec<(Job, Instant)>,
+        state: State,
+        max_batch_size: Option<usize>,
+        // richiesta di partire subito con un batch, senza aspettare l'intervallo
+        flush: Option<Trigger>,
+        on_batch: Option<Callback>,
+    }
+
+    type Tasks = Arc<Mutex<Inner>>;
+
     pub struct BatchedExecutor {
-        tasks: Arc<Mutex<(Vec<Box <dyn FnOnce() + Send + 'static>>, State)>>,
+        tasks: Tasks,
         condvar: Arc<Condvar>,
         jh: Option<JoinHandle<()>>,
+        workers: Vec<JoinHandle<()>>,
+        alive: Alive,
+        clock: Arc<dyn Clock>,
+        // registrato sul clock, che lo tiene solo finché esiste l'executor
+        _on_advance: Listener,
+    }
+
+    // numero di thread (batch e worker) ancora attivi, usato da await_termination
+    type Alive = Arc<(Mutex<usize>, Condvar)>;
+
+    struct AliveGuard(Alive);
+
+    impl Drop for AliveGuard {
+        fn drop(&mut self) {
+            *self.0.0.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+            self.0.1.notify_all();
+        }
+    }
+
+    // stato condiviso dai task di un batch: l'ultimo che termina chiama la callback
+    struct Batch {
+        remaining: AtomicUsize,
+        panicked: AtomicUsize,
+        trigger: Trigger,
+        size: usize,
+        queued: Duration,
+        started: Instant,
+        on_batch: Option<Callback>,
+        clock: Arc<dyn Clock>,
+    }
+
+    impl Batch {
+        fn done(&self, panicked: bool) {
+            if panicked {
+                self.panicked.fetch_add(1, Ordering::SeqCst);
+            }
+            if self.remaining.fetch_sub(1, Ordering::SeqCst) == 1 && let Some(cb) = &self.on_batch {
+                let stats = BatchStats {
+                    trigger: self.trigger,
+                    size: self.size,
+                    panicked: self.panicked.load(Ordering::SeqCst),
+                    queued: self.queued,
+                    elapsed: self.clock.now() - self.started,
+                };
+                cb(&stats);
+            }
+        }
     }
 
     impl Drop for BatchedExecutor {
         fn drop(&mut self) {
-            self.close(true);
-            self.jh.take().unwrap().join().unwrap();
+            // se close(false) è già stato chiamato l'ultimo batch viene comunque eseguito
+            if self.tasks.lock().unwrap().state == State::Open {
+                self.close(true);
+            }
+            // il thread dei batch chiude il canale uscendo, i worker terminano dopo aver svuotato il canale.
+            // i panic dei task sono già isolati: un errore di join non va propagato dal drop
+            if let Some(jh) = self.jh.take() {
+                let _ = jh.join();
+            }
+            for w in self.workers.drain(..) {
+                let _ = w.join();
+            }
         }
     }
 
     impl BatchedExecutor {
-        fn new(batch_interval: Duration) -> Self {
-            let mut tasks = Arc::new(Mutex::<(Vec<Box<dyn FnOnce() + Send + 'static>>, State)>::new((Vec::new(), State::Open)));
+        pub fn new(batch_interval: Duration) -> Self {
+            Self::with_workers(batch_interval, 1)
+        }
+
+        // i task di ogni batch vengono distribuiti su `workers` thread
+        pub fn with_workers(batch_interval: Duration, workers: usize) -> Self {
+            Self::with_clock_and_workers(batch_interval, Arc::new(SystemClock), workers)
+        }
+
+        // l'intervallo dei batch e le statistiche seguono `clock`
+        pub fn with_clock(batch_interval: Duration, clock: Arc<dyn Clock>) -> Self {
+            Self::with_clock_and_workers(batch_interval, clock, 1)
+        }
+
+        pub fn with_clock_and_workers(batch_interval: Duration, clock: Arc<dyn Clock>, workers: usize) -> Self {
+            assert!(workers > 0, "workers must be greater than zero");
+            let tasks: Tasks = Arc::new(Mutex::new(Inner { tasks: Vec::new(), state: State::Open, max_batch_size: None, flush: None, on_batch: None }));
             let condvar = Arc::new(Condvar::new());
 
-            let mut task_c = Arc::clone(&tasks);
+            // con un clock finto il tempo avanza senza notifiche: il thread dei batch va svegliato a mano
+            let (t_c, cond_c) = (Arc::clone(&tasks), Arc::clone(&condvar));
+            let on_advance: Listener = Arc::new(move || {
+                let _t = t_c.lock().unwrap();
+                cond_c.notify_all();
+            });
+            clock.on_advance(&on_advance);
+
+            let alive: Alive = Arc::new((Mutex::new(workers + 1), Condvar::new()));
+            let (tx, rx) = mpsc::channel::<Job>();
+            let rx = Arc::new(Mutex::new(rx));
+            let workers = (0..workers).map(|_| {
+                let rx = Arc::clone(&rx);
+                let guard = AliveGuard(Arc::clone(&alive));
+                thread::spawn(move || {
+                    let _guard = guard;
+                    loop {
+                        let f = rx.lock().unwrap().recv();
+                        match f {
+                            // un task (o una callback) che va in panic non deve far terminare il worker
+                            Ok(f) => { let _ = panic::catch_unwind(AssertUnwindSafe(f)); },
+                            Err(_) => break,
+                        }
+                    }
+                })
+            }).collect();
+
+            let task_c = Arc::clone(&tasks);
             let cond_c = Arc::clone(&condvar);
+            let clock_c = Arc::clone(&clock);
 
+            let guard = AliveGuard(Arc::clone(&alive));
+            // i batch a tempo seguono una cadenza fissa, indipendente da quelli per dimensione o flush
+            let mut next_tick = clock.now() + batch_interval;
             let jh = thread::spawn(move || {
+                let _guard = guard;
                 loop {
                     let mut t = task_c.lock().unwrap();
-            
-                    if t.1 == State::Close {
-                        break;
-                    }
-                    else {
-                        while t.0.len() == 0 {
-                            t = cond_c.wait(t).unwrap();
+                    // DrainNow, Abort e flush svegliano subito il thread, altrimenti si attende il prossimo tick
+                    let timed_out = loop {
+                        if t.state > State::Drain || t.flush.is_some() {
+                            break false;
                         }
-                        t = cond_c.wait_timeout(t, batch_interval).unwrap().0;
+                        if clock_c.now() >= next_tick {
+                            break true;
+                        }
+                        t = match clock_c.wait_for(next_tick) {
+                            Some(d) => cond_c.wait_timeout(t, d).unwrap().0,
+                            None => cond_c.wait(t).unwrap(),
+                        };
+                    };
+
+                    let closed = t.state != State::Open && (t.state > State::Drain || timed_out);
+                    let trigger = if closed {
+                        Trigger::Close
+                    } else if let Some(trigger) = t.flush.take() {
+                        trigger
+                    } else {
+                        next_tick += batch_interval;
+                        Trigger::Interval
+                    };
 
-                        let f = t.0.pop().unwrap();
-                        f();
+                    // il batch viene estratto sotto lock ma eseguito dai worker, senza bloccare submit.
+                    // un batch per dimensione prende solo batch pieni, il resto aspetta il prossimo
+                    let max = t.max_batch_size.unwrap_or(usize::MAX);
+                    let n = if trigger == Trigger::Size { t.tasks.len() - t.tasks.len() % max } else { t.tasks.len() };
+                    let pending: Vec<_> = t.tasks.drain(..n).collect();
+                    let on_batch = t.on_batch.clone();
+                    drop(t);
+
+                    let mut pending = pending.into_iter().peekable();
+                    while pending.peek().is_some() {
+                        let batch: Vec<_> = pending.by_ref().take(max).collect();
+                        let started = clock_c.now();
+                        let info = Arc::new(Batch {
+                            remaining: AtomicUsize::new(batch.len()),
+                            panicked: AtomicUsize::new(0),
+                            trigger,
+                            size: batch.len(),
+                            queued: started - batch[0].1,
+                            started,
+                            on_batch: on_batch.clone(),
+                            clock: Arc::clone(&clock_c),
+                        });
+                        for (f, _) in batch {
+                            let info = Arc::clone(&info);
+                            let job: Job = Box::new(move || {
+                                let panicked = panic::catch_unwind(AssertUnwindSafe(f)).is_err();
+                                info.done(panicked);
+                            });
+                            if tx.send(job).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    if closed {
+                        break;
                     }
                 }
             });
 
             Self {
-                tasks: tasks,
-                condvar: condvar,
+                tasks,
+                condvar,
                 jh: Some(jh),
+                workers,
+                alive,
+                clock,
+                _on_advance: on_advance,
             }
         }
+
+        // un batch parte appena in coda ci sono `max` task, senza aspettare l'intervallo;
+        // nessun batch supera comunque questa dimensione
+        pub fn with_max_batch_size(self, max: usize) -> Self {
+            assert!(max > 0, "max batch size must be greater than zero");
+            self.tasks.lock().unwrap().max_batch_size = Some(max);
+            self
+        }
+
+        // `callback` viene chiamata su un worker quando tutti i task di un batch sono terminati
+        pub fn on_batch<C>(self, callback: C) -> Self where C: Fn(&BatchStats) + Send + Sync + 'static {
+            self.tasks.lock().unwrap().on_batch = Some(Arc::new(callback));
+            self
+        }
     
-        fn submit<F: FnOnce() + Send + 'static>(&self, f: F) -> bool {
+        pub fn submit<F: FnOnce() + Send + 'static>(&self, f: F) -> bool {
             let mut t = self.tasks.lock().unwrap();
-            if t.1 == State::Close {
+            if t.state != State::Open {
                 return false;
             }
-            t.0.push(Box::new(f));
-            self.condvar.notify_all();
-            return true;
+            t.tasks.push((Box::new(f), self.clock.now()));
+            if t.max_batch_size.is_some_and(|max| t.tasks.len() >= max) && t.flush.is_none() {
+                t.flush = Some(Trigger::Size);
+                self.condvar.notify_all();
+            }
+            true
         }
-        
-        fn close(&self, drop_pending: bool) {
+
+        // fa partire subito un batch con i task in coda; non attende che siano eseguiti
+        pub fn flush(&self) {
             let mut t = self.tasks.lock().unwrap();
-            t.1 = State::Close;
-            if drop_pending == true {
-                t.0.clear();
+            if t.state == State::Open && !t.tasks.is_empty() {
+                t.flush = Some(Trigger::Flush);
                 self.condvar.notify_all();
             }
         }
+        
+        // close(true) equivale a CloseMode::Abort scartando i task, close(false) a CloseMode::Drain
+        pub fn close(&self, drop_pending: bool) {
+            self.close_with(if drop_pending { CloseMode::Abort } else { CloseMode::Drain });
+        }
+
+        // chiude l'executor: da qui in poi submit restituisce false. Con Abort restituisce,
+        // in ordine di submit, i task che non sono ancora stati passati ai worker
+        pub fn close_with(&self, mode: CloseMode) -> Vec<Job> {
+            let mut t = self.tasks.lock().unwrap();
+            let next = match mode {
+                CloseMode::Drain => State::Drain,
+                CloseMode::DrainNow => State::DrainNow,
+                CloseMode::Abort => State::Close,
+            };
+            if next > t.state {
+                t.state = next;
+            }
+            self.condvar.notify_all();
+
+            if mode == CloseMode::Abort {
+                t.tasks.drain(..).map(|(f, _)| f).collect()
+            } else {
+                Vec::new()
+            }
+        }
+
+        // attende al massimo `timeout` che l'executor, dopo una close, abbia eseguito l'ultimo batch;
+        // restituisce true se tutti i thread sono terminati
+        pub fn await_termination(&self, timeout: Duration) -> bool {
+            let (alive, cv) = &*self.alive;
+            let n = alive.lock().unwrap();
+            *cv.wait_timeout_while(n, timeout, |n| *n > 0).unwrap().0 == 0
+        }
     }
 }
 
@@ -128,3 +458,212 @@ pub mod executor {
 fn main() {
     println!("Hello, world!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::clock::MockClock;
+    use super::executor::{BatchedExecutor, CloseMode, Trigger};
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_tasks_run_in_batches() {
+        let clock = MockClock::new();
+        let (stats_tx, stats_rx) = mpsc::channel();
+        let exec = BatchedExecutor::with_clock(Duration::from_millis(100), clock.clone())
+            .on_batch(move |s| stats_tx.send((s.trigger, s.size, s.queued)).unwrap());
+        let (tx, rx) = mpsc::channel();
+        for i in 0..3 {
+            let tx = tx.clone();
+            assert!(exec.submit(move || tx.send(i).unwrap()));
+        }
+
+        clock.advance(Duration::from_millis(99));
+        assert!(rx.try_recv().is_err());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(stats_rx.recv_timeout(Duration::from_secs(1)), Ok((Trigger::Interval, 3, Duration::from_millis(100))));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        // il tick successivo cade a 200ms, indipendentemente da quando arrivano i task
+        clock.advance(Duration::from_millis(50));
+        exec.submit(|| {});
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(stats_rx.recv_timeout(Duration::from_secs(1)), Ok((Trigger::Interval, 1, Duration::from_millis(50))));
+    }
+
+    #[test]
+    fn test_slow_task_does_not_block_the_batch() {
+        let exec = BatchedExecutor::with_workers(Duration::from_millis(20), 2);
+        let (tx, rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        // con un solo worker il primo task resterebbe bloccato fino al timeout
+        exec.submit(move || done_tx.send(rx.recv_timeout(Duration::from_secs(2)).is_ok()).unwrap());
+        exec.submit(move || tx.send(()).unwrap());
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(3)), Ok(true));
+    }
+
+    #[test]
+    fn test_panic_does_not_kill_the_executor() {
+        let exec = BatchedExecutor::new(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+        exec.submit(|| panic!("boom"));
+        exec.submit(move || tx.send(1).unwrap());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+    }
+
+    #[test]
+    fn test_close_runs_or_drops_pending() {
+        for (drop_pending, expected) in [(false, 2), (true, 0)] {
+            let exec = BatchedExecutor::new(Duration::from_millis(50));
+            let (tx, rx) = mpsc::channel();
+            for _ in 0..2 {
+                let tx = tx.clone();
+                exec.submit(move || tx.send(()).unwrap());
+            }
+            drop(tx);
+            exec.close(drop_pending);
+            assert!(!exec.submit(|| {}));
+            drop(exec);
+            assert_eq!(rx.iter().count(), expected);
+        }
+    }
+
+    #[test]
+    fn test_close_drain_waits_for_the_batch() {
+        let clock = MockClock::new();
+        let (stats_tx, stats_rx) = mpsc::channel();
+        let exec = BatchedExecutor::with_clock(Duration::from_millis(200), clock.clone())
+            .on_batch(move |s| stats_tx.send((s.trigger, s.queued)).unwrap());
+        let (tx, rx) = mpsc::channel();
+        exec.submit(move || tx.send(()).unwrap());
+
+        assert!(exec.close_with(CloseMode::Drain).is_empty());
+        assert!(!exec.submit(|| {}));
+        clock.advance(Duration::from_millis(199));
+        assert!(!exec.await_termination(Duration::from_millis(20)));
+        assert!(rx.try_recv().is_err());
+        clock.advance(Duration::from_millis(1));
+        assert!(exec.await_termination(Duration::from_secs(2)));
+        assert_eq!(rx.try_recv(), Ok(()));
+        assert_eq!(stats_rx.try_recv(), Ok((Trigger::Close, Duration::from_millis(200))));
+    }
+
+    #[test]
+    fn test_close_drain_now_runs_pending_immediately() {
+        let exec = BatchedExecutor::with_workers(Duration::from_secs(60), 2);
+        let (tx, rx) = mpsc::channel();
+        for i in 0..4 {
+            let tx = tx.clone();
+            exec.submit(move || tx.send(i).unwrap());
+        }
+        drop(tx);
+
+        exec.close_with(CloseMode::DrainNow);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        let mut got: Vec<_> = rx.iter().collect();
+        got.sort();
+        assert_eq!(got, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_close_abort_returns_pending_tasks() {
+        let exec = BatchedExecutor::new(Duration::from_secs(60));
+        let (tx, rx) = mpsc::channel();
+        for i in 0..3 {
+            let tx = tx.clone();
+            exec.submit(move || tx.send(i).unwrap());
+        }
+
+        let pending = exec.close_with(CloseMode::Abort);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(pending.len(), 3);
+        for f in pending {
+            f();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_size_triggered_batches() {
+        let (stats_tx, stats_rx) = mpsc::channel();
+        let exec = BatchedExecutor::new(Duration::from_secs(60))
+            .with_max_batch_size(3)
+            .on_batch(move |s| stats_tx.send((s.trigger, s.size)).unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        for i in 0..7 {
+            let tx = tx.clone();
+            exec.submit(move || tx.send(i).unwrap());
+        }
+        let recv = || stats_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(recv(), (Trigger::Size, 3));
+        assert_eq!(recv(), (Trigger::Size, 3));
+        assert!(stats_rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        exec.flush();
+        assert_eq!(recv(), (Trigger::Flush, 1));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), (0..7).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_batch_stats() {
+        let (stats_tx, stats_rx) = mpsc::channel();
+        let exec = BatchedExecutor::with_workers(Duration::from_secs(60), 2)
+            .on_batch(move |s| stats_tx.send(s.clone()).unwrap());
+        let ran = Arc::new(Mutex::new(0));
+
+        for i in 0..4 {
+            let ran = Arc::clone(&ran);
+            exec.submit(move || {
+                *ran.lock().unwrap() += 1;
+                if i % 2 == 1 {
+                    panic!("task {} failed", i);
+                }
+            });
+        }
+        exec.flush();
+        let stats = stats_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        // la callback arriva solo quando tutti i task del batch sono terminati
+        assert_eq!(*ran.lock().unwrap(), 4);
+        assert_eq!((stats.trigger, stats.size, stats.panicked), (Trigger::Flush, 4, 2));
+        assert!(stats.elapsed < Duration::from_secs(1));
+
+        // senza task in coda l'ultimo batch è vuoto e non produce statistiche
+        exec.close_with(CloseMode::DrainNow);
+        drop(exec);
+        assert!(stats_rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_task_can_submit_while_batch_runs() {
+        let (stats_tx, stats_rx) = mpsc::channel();
+        let exec = Arc::new(BatchedExecutor::new(Duration::from_secs(60)).on_batch(move |s| stats_tx.send(s.size).unwrap()));
+        let (tx, rx) = mpsc::channel();
+
+        // il batch gira fuori dal mutex: submit dall'interno di un task non va in deadlock
+        let e = Arc::clone(&exec);
+        exec.submit(move || {
+            let tx = tx.clone();
+            assert!(e.submit(move || tx.send("inner").unwrap()));
+        });
+        exec.flush();
+        assert_eq!(stats_rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+        exec.flush();
+        assert_eq!(stats_rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok("inner"));
+    }
+
+    #[test]
+    fn test_final_batch_on_close_reports_close_trigger() {
+        let (stats_tx, stats_rx) = mpsc::channel();
+        let exec = BatchedExecutor::new(Duration::from_secs(60)).on_batch(move |s| stats_tx.send((s.trigger, s.size)).unwrap());
+        exec.submit(|| {});
+        exec.submit(|| {});
+        exec.close_with(CloseMode::DrainNow);
+        assert!(exec.await_termination(Duration::from_secs(1)));
+        assert_eq!(stats_rx.try_recv(), Ok((Trigger::Close, 2)));
+    }
+}
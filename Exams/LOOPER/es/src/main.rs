@@ -1,9 +1,48 @@
 
 pub mod looper {
-    use std::{collections::VecDeque, sync::{Arc, Condvar, Mutex}, thread::{self, JoinHandle}};
+    use std::{sync::{Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Priority {
+        Low,
+        Normal,
+        High,
+    }
+
+    // ogni `DEFAULT_AGING` di attesa un messaggio sale di un livello di priorità
+    pub const DEFAULT_AGING: Duration = Duration::from_secs(1);
+
+    struct Entry<Msg> {
+        msg: Msg,
+        priority: Priority,
+        seq: u64,
+        sent: Instant,
+    }
+
+    struct Inner<Msg> {
+        queue: Vec<Entry<Msg>>,
+        next_seq: u64,
+        aging: Duration,
+        // impostato dal drop: il thread elabora i messaggi rimasti e poi chiama cleanup
+        closed: bool,
+    }
+
+    impl<Msg> Inner<Msg> {
+        // prossimo messaggio da elaborare: priorità più alta, tenendo conto dell'attesa, poi ordine di invio
+        fn pop(&mut self) -> Option<Msg> {
+            let now = Instant::now();
+            let aging = self.aging.as_nanos().max(1);
+            let rank = |e: &Entry<Msg>| e.priority as u128 + now.duration_since(e.sent).as_nanos() / aging;
+            let i = (0..self.queue.len()).max_by(|&a, &b| {
+                let (a, b) = (&self.queue[a], &self.queue[b]);
+                rank(a).cmp(&rank(b)).then(b.seq.cmp(&a.seq))
+            })?;
+            Some(self.queue.swap_remove(i).msg)
+        }
+    }
 
     pub struct Looper<Msg: Send + Sync + 'static> {
-        queue: Arc<Mutex<VecDeque<Option<Msg>>>>,
+        queue: Arc<Mutex<Inner<Msg>>>,
         condvar: Arc<Condvar>,
         jh: Option<JoinHandle<()>>,
     }
@@ -11,7 +50,7 @@ pub mod looper {
     impl<Msg: Send + Sync + 'static> Drop for Looper<Msg> {
         fn drop(&mut self) {
             let mut q = self.queue.lock().unwrap();
-            q.push_back(None);
+            q.closed = true;
             drop(q);
             self.condvar.notify_all();
             self.jh.take().unwrap().join().unwrap();
@@ -19,21 +58,19 @@ pub mod looper {
     }
 
     impl<Msg: Send + Sync + 'static>Looper<Msg> {
-        pub fn new<F, C>(process: F, clenup: C) -> Self where F: Fn(Msg) -> () + Send + Sync + 'static, C: Fn() -> () + Send + Sync + 'static {
-            let mut queue = Arc::new(Mutex::new(VecDeque::<Option<Msg>>::new()));
+        pub fn new<F, C>(process: F, clenup: C) -> Self where F: Fn(Msg) + Send + Sync + 'static, C: Fn() + Send + Sync + 'static {
+            let queue = Arc::new(Mutex::new(Inner { queue: Vec::new(), next_seq: 0, aging: DEFAULT_AGING, closed: false }));
             let cond = Arc::new(Condvar::new());
 
             let cond_c = Arc::clone(&cond);
-            let mut q_c = Arc::clone(&queue);
+            let q_c = Arc::clone(&queue);
 
             let jh = thread::spawn(move || {
                 loop {
-                    let mut q = q_c.lock().unwrap();
-                    if q.len() == 0 {
-                        q = cond_c.wait_while(q, |q| q.len() == 0).unwrap();
-                    }
+                    let q = q_c.lock().unwrap();
+                    let mut q = cond_c.wait_while(q, |q| q.queue.is_empty() && !q.closed).unwrap();
 
-                    let m = q.pop_front().unwrap();
+                    let m = q.pop();
                     drop(q);
                     match m {
                         Some(msg) => {
@@ -48,15 +85,28 @@ pub mod looper {
             });
 
             Self {
-                queue: queue,
+                queue,
                 condvar: cond,
                 jh: Some(jh),
             }
         } 
 
+        // intervallo di attesa dopo cui un messaggio guadagna un livello di priorità
+        pub fn with_aging(self, step: Duration) -> Self {
+            self.queue.lock().unwrap().aging = step;
+            self
+        }
+
         pub fn send(&self, msg: Msg) {
+            self.send_with_priority(msg, Priority::Normal);
+        }
+
+        // a parità di priorità (dopo l'aging) i messaggi vengono elaborati in ordine di invio
+        pub fn send_with_priority(&self, msg: Msg, priority: Priority) {
             let mut queue = self.queue.lock().unwrap();
-            queue.push_back(Some(msg));
+            let seq = queue.next_seq;
+            queue.next_seq += 1;
+            queue.queue.push(Entry { msg, priority, seq, sent: Instant::now() });
             self.condvar.notify_all();
         }
     }
@@ -84,3 +134,63 @@ fn main() {
     println!("Fine main, Looper verrà droppato e cleanup chiamato.");
 }
 
+#[cfg(test)]
+mod tests {
+    use super::looper::{Looper, Priority};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    // il primo messaggio blocca il looper finché `release` non viene chiamata, così gli altri si accumulano
+    fn blocked_looper(aging: Duration) -> (Looper<&'static str>, mpsc::Sender<()>, mpsc::Receiver<&'static str>) {
+        let (out_tx, out_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = std::sync::Mutex::new(release_rx);
+        let looper = Looper::new(move |msg: &'static str| {
+            if msg == "block" {
+                release_rx.lock().unwrap().recv().unwrap();
+            } else {
+                out_tx.send(msg).unwrap();
+            }
+        }, || {}).with_aging(aging);
+        looper.send("block");
+        thread::sleep(Duration::from_millis(20));
+        (looper, release_tx, out_rx)
+    }
+
+    #[test]
+    fn test_messages_are_ordered_by_priority_then_fifo() {
+        let (looper, release, out) = blocked_looper(Duration::from_secs(3600));
+        looper.send_with_priority("low", Priority::Low);
+        looper.send("normal 1");
+        looper.send_with_priority("high", Priority::High);
+        looper.send("normal 2");
+        release.send(()).unwrap();
+        drop(looper);
+        assert_eq!(out.iter().collect::<Vec<_>>(), vec!["high", "normal 1", "normal 2", "low"]);
+    }
+
+    #[test]
+    fn test_aging_prevents_starvation() {
+        let (looper, release, out) = blocked_looper(Duration::from_millis(10));
+        looper.send_with_priority("low", Priority::Low);
+        thread::sleep(Duration::from_millis(100));
+        looper.send_with_priority("high 1", Priority::High);
+        looper.send_with_priority("high 2", Priority::High);
+        release.send(()).unwrap();
+        drop(looper);
+        assert_eq!(out.iter().collect::<Vec<_>>(), vec!["low", "high 1", "high 2"]);
+    }
+
+    #[test]
+    fn test_pending_messages_are_processed_before_cleanup() {
+        let (tx, rx) = mpsc::channel();
+        let t = tx.clone();
+        let looper = Looper::new(move |n: u32| t.send(n).unwrap(), move || tx.send(0).unwrap());
+        for n in 1..=3 {
+            looper.send(n);
+        }
+        drop(looper);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![1, 2, 3, 0]);
+    }
+}
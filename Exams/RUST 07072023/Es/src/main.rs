@@ -1,57 +1,196 @@
 
 
 pub mod queue {
-    use std::{collections::{btree_set::Intersection, VecDeque}, sync::{Arc, Condvar, Mutex}, time::Instant};
+    use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}, sync::{Arc, Condvar, Mutex}, time::Instant};
 
-    //Metto Option<T> perchè quando scade inserisco None
-    pub struct DelayedQueue<T: Send + Copy> {
-        queue: Arc<Mutex<Vec<(T, Instant)>>>,
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Handle(u64);
+
+    // min-heap su (istante, sequenza, id): a parità di istante vale l'ordine di inserimento.
+    // il valore vive nella mappa; un nodo dell'heap è valido solo se la sua sequenza è quella corrente
+    struct Inner<T> {
+        heap: BinaryHeap<Reverse<(Instant, u64, u64)>>,
+        items: HashMap<u64, (T, u64)>,
+        next_seq: u64,
+        closed: bool,
+    }
+
+    impl<T> Inner<T> {
+        fn head(&mut self) -> Option<(u64, Instant)> {
+            while let Some(Reverse((i, seq, id))) = self.heap.peek() {
+                if matches!(self.items.get(id), Some((_, s)) if s == seq) {
+                    return Some((*id, *i));
+                }
+                self.heap.pop();
+            }
+            None
+        }
+
+        fn pop(&mut self, id: u64) -> Option<T> {
+            self.heap.pop();
+            self.items.remove(&id).map(|(t, _)| t)
+        }
+
+        fn compact(&mut self) {
+            if self.heap.len() > 2 * self.items.len() + 16 {
+                let items = &self.items;
+                self.heap.retain(|Reverse((_, seq, id))| matches!(items.get(id), Some((_, s)) if s == seq));
+            }
+        }
+    }
+
+    pub struct DelayedQueue<T: Send> {
+        queue: Arc<Mutex<Inner<T>>>,
         condvar: Arc<Condvar>,
     }
 
-    impl<T: Send + Copy> DelayedQueue<T> {
+    impl<T: Send> Default for DelayedQueue<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T: Send> DelayedQueue<T> {
         pub fn new() -> Self {
             Self {
-                queue: Arc::new(Mutex::new(Vec::new())),
+                queue: Arc::new(Mutex::new(Inner { heap: BinaryHeap::new(), items: HashMap::new(), next_seq: 0, closed: false })),
                 condvar: Arc::new(Condvar::new()),
             }
         }
 
-        pub fn offer(&mut self, t: T, i: Instant){
+        // restituisce None se la coda è stata chiusa
+        pub fn offer(&self, t: T, i: Instant) -> Option<Handle> {
             let mut q = self.queue.lock().unwrap();
-            q.push((t, i));
+            if q.closed {
+                return None;
+            }
+            let id = q.next_seq;
+            q.next_seq += 1;
+            q.items.insert(id, (t, id));
+            q.heap.push(Reverse((i, id, id)));
             self.condvar.notify_all();
-            q.sort_by( |a, b| b.1.cmp(&a.1)); //decrescente perchè la pop toglie l'ultimo valore
+            Some(Handle(id))
         }
 
+        pub fn remove(&self, h: Handle) -> Option<T> {
+            let mut q = self.queue.lock().unwrap();
+            let t = q.items.remove(&h.0).map(|(t, _)| t);
+            q.compact();
+            self.condvar.notify_all();
+            t
+        }
+
+        pub fn reschedule(&self, h: Handle, i: Instant) -> bool {
+            let mut q = self.queue.lock().unwrap();
+            let seq = q.next_seq;
+            let Some(item) = q.items.get_mut(&h.0) else {
+                return false;
+            };
+            item.1 = seq;
+            q.next_seq += 1;
+            q.heap.push(Reverse((i, seq, h.0)));
+            q.compact();
+            self.condvar.notify_all();
+            true
+        }
+
+        // l'elemento viene tolto dalla coda: con più consumatori ognuno lo riceve una sola volta
         pub fn take(&self) -> Option<T> {
+            self.wait(false)
+        }
+
+        pub fn try_take(&self) -> Option<T> {
+            let mut q = self.queue.lock().unwrap();
+            match q.head() {
+                Some((id, i)) if i <= Instant::now() => q.pop(id),
+                _ => None,
+            }
+        }
+
+        // le offer successive falliscono; gli iteratori consumano gli elementi rimasti e poi terminano
+        pub fn close(&self) {
+            let mut q = self.queue.lock().unwrap();
+            q.closed = true;
+            self.condvar.notify_all();
+        }
+
+        pub fn size(&self) -> usize {
+            self.len()
+        }
+
+        pub fn len(&self) -> usize {
+            let q = self.queue.lock().unwrap();
+            q.items.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        // iteratore bloccante: attende anche l'arrivo di nuovi elementi, termina quando la coda è chiusa e vuota
+        pub fn iter(&self) -> Iter<'_, T> {
+            Iter { queue: self }
+        }
+
+        pub fn drain_ready(&self) -> DrainReady<'_, T> {
+            DrainReady { queue: self }
+        }
+
+        // con `block` a false una coda vuota restituisce subito None, altrimenti si attende un elemento
+        // finché la coda non è chiusa
+        fn wait(&self, block: bool) -> Option<T> {
             let mut q = self.queue.lock().unwrap();
 
             loop {
-                if q.is_empty() {
-                    return None;
-                }
-                
-                let val = q.last().unwrap();
-                let (t, i) = val;
-                if *i <= Instant::now() {
-                    return Some(*t);
-                }
-                else {
-                    let delay = *i - Instant::now();
-                    let (new_q, _) = self.condvar.wait_timeout(q, delay).unwrap();
-                    q = new_q;
+                match q.head() {
+                    Some((id, i)) => {
+                        let now = Instant::now();
+                        if i <= now {
+                            return q.pop(id);
+                        }
+                        let (new_q, _) = self.condvar.wait_timeout(q, i - now).unwrap();
+                        q = new_q;
+                    },
+                    None if block && !q.closed => q = self.condvar.wait(q).unwrap(),
+                    None => return None,
                 }
             }
         }
+    }
 
-        pub fn size(&self) -> usize {
-            let q = self.queue.lock().unwrap();
-            q.len()
+    pub struct Iter<'a, T: Send> {
+        queue: &'a DelayedQueue<T>,
+    }
+
+    impl<T: Send> Iterator for Iter<'_, T> {
+        type Item = T;
+
+        fn next(&mut self) -> Option<T> {
+            self.queue.wait(true)
         }
     }
-}
 
+    impl<'a, T: Send> IntoIterator for &'a DelayedQueue<T> {
+        type Item = T;
+        type IntoIter = Iter<'a, T>;
+
+        fn into_iter(self) -> Iter<'a, T> {
+            self.iter()
+        }
+    }
+
+    pub struct DrainReady<'a, T: Send> {
+        queue: &'a DelayedQueue<T>,
+    }
+
+    impl<T: Send> Iterator for DrainReady<'_, T> {
+        type Item = T;
+
+        fn next(&mut self) -> Option<T> {
+            self.queue.try_take()
+        }
+    }
+}
 
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -74,7 +213,7 @@ fn main() {
     let consumer = {
         let q = queue.clone();
         thread::spawn(move || {
-            while let Some(msg) = q.take() {
+            for msg in &*q {
                 println!("Ricevuto: {}", msg);
             }
         })
@@ -83,8 +222,104 @@ fn main() {
     producer.join().unwrap();
     // aspetta un po' che il consumer prenda i messaggi
     thread::sleep(Duration::from_secs(3));
+    queue.close();
+    consumer.join().unwrap();
 
     // Poiché consumer può bloccarsi su take() se la coda è vuota,
     // eventualmente si può interrompere il consumer in altro modo (non implementato qui).
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_earliest_instant_first_and_fifo_on_ties() {
+        let q = DelayedQueue::new();
+        let now = Instant::now();
+        q.offer(3, now + Duration::from_millis(30));
+        q.offer(1, now);
+        q.offer(2, now);
+        assert_eq!(q.take(), Some(1));
+        assert_eq!(q.take(), Some(2));
+        assert_eq!(q.size(), 1);
+    }
+
+    #[test]
+    fn test_remove_and_reschedule() {
+        let q = DelayedQueue::new();
+        let now = Instant::now();
+        let a = q.offer('a', now).unwrap();
+        let b = q.offer('b', now + Duration::from_secs(10)).unwrap();
+
+        assert!(q.reschedule(b, now - Duration::from_millis(1)));
+        assert_eq!(q.take(), Some('b'));
+        assert_eq!(q.remove(b), None);
+        assert_eq!(q.remove(a), Some('a'));
+        assert!(!q.reschedule(a, now));
+        assert_eq!(q.take(), None);
+    }
+
+    #[test]
+    fn test_drain_ready_and_len() {
+        let q = DelayedQueue::new();
+        let now = Instant::now();
+        q.offer(1, now);
+        q.offer(2, now + Duration::from_secs(10));
+        q.offer(3, now);
+        assert_eq!(q.drain_ready().collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(q.len(), 1);
+    }
+
+    #[test]
+    fn test_offer_after_close_is_rejected() {
+        let q = DelayedQueue::new();
+        let now = Instant::now();
+        assert!(q.offer(1, now).is_some());
+        q.close();
+        assert_eq!(q.offer(2, now), None);
+        assert_eq!(q.len(), 1);
+        assert_eq!(q.take(), Some(1));
+        assert_eq!(q.take(), None);
+    }
+
+    #[test]
+    fn test_iter_drains_items_left_after_close() {
+        let q = DelayedQueue::new();
+        let now = Instant::now();
+        q.offer(2, now + Duration::from_millis(20));
+        q.offer(1, now);
+        q.close();
+        assert_eq!(q.iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_shared_between_producers_and_consumers() {
+        let q = Arc::new(DelayedQueue::<u32>::new());
+        let consumers: Vec<_> = (0..4).map(|_| {
+            let q = Arc::clone(&q);
+            thread::spawn(move || q.iter().collect::<Vec<_>>())
+        }).collect();
+
+        let producers: Vec<_> = (0..4u32).map(|p| {
+            let q = Arc::clone(&q);
+            thread::spawn(move || {
+                for i in 0..250u32 {
+                    q.offer(p * 250 + i, Instant::now() + Duration::from_micros((i as u64 * 13) % 2000));
+                }
+            })
+        }).collect();
+        for p in producers {
+            p.join().unwrap();
+        }
+
+        while !q.is_empty() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        q.close();
+
+        let mut all: Vec<u32> = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect();
+        all.sort();
+        assert_eq!(all, (0..1000).collect::<Vec<_>>());
+    }
+}
@@ -4,46 +4,185 @@ use crate::limiter::ExecutionLimiter;
 
 
 pub mod limiter {
+    use std::collections::VecDeque;
+    use std::fmt;
     use std::sync::{Arc, Condvar, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug, PartialEq)]
+    pub enum LimiterError {
+        // f ha restituito errore
+        Failed,
+        // nessun permesso si è liberato entro il timeout
+        Timeout,
+        // try_execute/try_acquire: nessun permesso libero subito
+        WouldBlock,
+    }
+
+    impl fmt::Display for LimiterError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                LimiterError::Failed => write!(f, "function failed"),
+                LimiterError::Timeout => write!(f, "timed out waiting for permit"),
+                LimiterError::WouldBlock => write!(f, "no permit available"),
+            }
+        }
+    }
+
+    struct State {
+        // può cambiare a runtime con set_limit
+        limit: usize,
+        in_use: usize,
+        // biglietti dei thread in attesa, in ordine di arrivo: entra solo il primo della coda
+        waiting: VecDeque<u64>,
+        next_ticket: u64,
+    }
 
     pub struct ExecutionLimiter {
-        counter: Arc<(Mutex<usize>, Condvar)>,
-        threshold: usize,
+        counter: Arc<(Mutex<State>, Condvar)>,
+    }
+
+    // permesso ottenuto con acquire: viene restituito al drop, anche se f va in panic
+    pub struct Permit<'a> {
+        limiter: &'a ExecutionLimiter,
+        weight: usize,
     }
 
     impl ExecutionLimiter {
         pub fn new(n: usize) -> Self {
+            assert!(n > 0, "limit must be greater than zero");
             Self {
-                counter: Arc::new((Mutex::new(0), Condvar::new())),
-                threshold: n,
+                counter: Arc::new((Mutex::new(State { limit: n, in_use: 0, waiting: VecDeque::new(), next_ticket: 0 }), Condvar::new())),
+            }
+        }
+
+        pub fn limit(&self) -> usize {
+            self.counter.0.lock().unwrap().limit
+        }
+
+        // riducendo il limite le esecuzioni in corso terminano normalmente, ma nessuno entra
+        // finché non si scende sotto la nuova soglia; aumentandolo vengono svegliati i thread in coda
+        pub fn set_limit(&self, n: usize) {
+            assert!(n > 0, "limit must be greater than zero");
+            let (lock, condvar) = &*self.counter;
+            lock.lock().unwrap().limit = n;
+            condvar.notify_all();
+        }
+
+        // attende un permesso senza consumare CPU, in ordine di arrivo
+        pub fn execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            self.execute_weighted(1, f)
+        }
+
+        // occupa `weight` permessi insieme; vedi acquire_weighted
+        pub fn execute_weighted<F, R>(&self, weight: usize, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.acquire_weighted(weight);
+            f().map_err(|_| LimiterError::Failed)
+        }
+
+        pub fn execute_timeout<F, R>(&self, timeout: Duration, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.acquire_timeout(timeout)?;
+            f().map_err(|_| LimiterError::Failed)
+        }
+
+        // non attende: WouldBlock se non c'è un permesso libero o qualcuno è già in coda
+        pub fn try_execute<F, R>(&self, f: F) -> Result<R, LimiterError> where F: FnOnce() -> Result<R, ()> {
+            let _permit = self.try_acquire()?;
+            f().map_err(|_| LimiterError::Failed)
+        }
+
+        pub fn acquire(&self) -> Permit<'_> {
+            self.acquire_weighted(1)
+        }
+
+        // chi chiede molti permessi non viene scavalcato da chi ne chiede pochi: la coda è FIFO.
+        // Un peso maggiore del limite viene eseguito da solo, quando non c'è nient'altro in corso
+        pub fn acquire_weighted(&self, weight: usize) -> Permit<'_> {
+            match self.acquire_until(weight, None) {
+                Ok(permit) => permit,
+                Err(_) => unreachable!(),
             }
         }
 
-        pub fn execute<F, R: Send + Sync + 'static>(&self, f: F) -> Result<R, String> where F: Fn() -> Result<R, ()> + Send + Sync + 'static, 
-        R: Send + Sync + 'static 
-        {
+        pub fn acquire_timeout(&self, timeout: Duration) -> Result<Permit<'_>, LimiterError> {
+            self.acquire_until(1, Some(Instant::now() + timeout))
+        }
+
+        pub fn try_acquire(&self) -> Result<Permit<'_>, LimiterError> {
+            let (lock, _) = &*self.counter;
+            let mut s = lock.lock().unwrap();
+            if !s.waiting.is_empty() || !s.fits(1) {
+                return Err(LimiterError::WouldBlock);
+            }
+            s.in_use += 1;
+            Ok(Permit { limiter: self, weight: 1 })
+        }
+
+        // esecuzioni in corso
+        pub fn running(&self) -> usize {
+            self.counter.0.lock().unwrap().in_use
+        }
+
+        fn acquire_until(&self, weight: usize, deadline: Option<Instant>) -> Result<Permit<'_>, LimiterError> {
+            assert!(weight > 0, "weight must be greater than zero");
             let (lock, condvar) = &*self.counter;
-            let mut cnt = lock.lock().unwrap();
-
-            cnt = condvar.wait_while(cnt, |c| *c >= self.threshold).unwrap();
-            *cnt += 1;
-
-            match f() {
-                Ok(r) => {
-                    *cnt -= 1;
-                    condvar.notify_one();
-                    return Ok(r);
-                },
-                Err(_) => {
-                    *cnt -= 1;
-                    condvar.notify_one();
-                    return Err(format!("Error! Function didn't work"));
-                },
+            let mut s = lock.lock().unwrap();
+            let ticket = s.next_ticket;
+            s.next_ticket += 1;
+            s.waiting.push_back(ticket);
+
+            loop {
+                if s.waiting.front() == Some(&ticket) && s.fits(weight) {
+                    s.waiting.pop_front();
+                    s.in_use += weight;
+                    // il prossimo in coda potrebbe trovare altri permessi liberi
+                    condvar.notify_all();
+                    return Ok(Permit { limiter: self, weight });
+                }
+                match deadline {
+                    None => s = condvar.wait(s).unwrap(),
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            // chi era dietro non deve restare bloccato dal biglietto abbandonato
+                            s.waiting.retain(|t| *t != ticket);
+                            condvar.notify_all();
+                            return Err(LimiterError::Timeout);
+                        }
+                        s = condvar.wait_timeout(s, deadline - now).unwrap().0;
+                    },
+                }
             }
         }
+
+        fn release(&self, weight: usize) {
+            let (lock, condvar) = &*self.counter;
+            lock.lock().unwrap_or_else(|e| e.into_inner()).in_use -= weight;
+            condvar.notify_all();
+        }
     }
-}
 
+    impl State {
+        fn fits(&self, weight: usize) -> bool {
+            self.in_use + weight <= self.limit || self.in_use == 0
+        }
+    }
+
+    impl Permit<'_> {
+        pub fn weight(&self) -> usize {
+            self.weight
+        }
+
+        // equivale al drop del permesso
+        pub fn release(self) {}
+    }
+
+    impl Drop for Permit<'_> {
+        fn drop(&mut self) {
+            self.limiter.release(self.weight);
+        }
+    }
+}
 
 fn main() {
     let limiter = Arc::new(ExecutionLimiter::new(4)); // Max 2 esecuzioni concorrenti
@@ -78,3 +217,39 @@ fn main() {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::limiter::{ExecutionLimiter, LimiterError};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_at_most_n_concurrent_executions() {
+        let limiter = Arc::new(ExecutionLimiter::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8).map(|i| {
+            let (limiter, running, peak) = (Arc::clone(&limiter), Arc::clone(&running), Arc::clone(&peak));
+            thread::spawn(move || limiter.execute(|| {
+                peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(i)
+            }))
+        }).collect();
+        for (i, h) in handles.into_iter().enumerate() {
+            assert_eq!(h.join().unwrap(), Ok(i));
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_timeout_waiting_for_permit() {
+        let limiter = ExecutionLimiter::new(1);
+        let _permit = limiter.acquire();
+        assert_eq!(limiter.execute_timeout(Duration::from_millis(10), || Ok(())), Err(LimiterError::Timeout));
+        assert_eq!(limiter.try_execute(|| Ok(())), Err(LimiterError::WouldBlock));
+    }
+}